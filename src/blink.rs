@@ -0,0 +1,80 @@
+use crate::io_backend::SharedIOBackend;
+use crate::io_config::BLINK_SETTLE_MS;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// toggles the flashing lamps of an aspect until it is stopped
+pub struct BlinkDriver {
+    flashing: Vec<usize>,
+    lit_since: Arc<Mutex<Option<Instant>>>,
+    stop_sender: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl BlinkDriver {
+    // pins are (lamp index, output pin) pairs, the lamps start in their on phase
+    pub fn start(
+        io: SharedIOBackend,
+        pins: Vec<(usize, String)>,
+        frequency: f64,
+        duty_cycle: f64,
+    ) -> Self {
+        let period = Duration::from_secs_f64(1.0 / frequency);
+        let on_time = period.mul_f64(duty_cycle.clamp(0.0, 1.0));
+        let off_time = period.saturating_sub(on_time);
+        let flashing = pins.iter().map(|(index, _)| *index).collect();
+        let lit_since = Arc::new(Mutex::new(None));
+        let thread_lit_since = lit_since.clone();
+        let (stop_sender, stop_receiver) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let mut lamps_on = true;
+            loop {
+                {
+                    let outputs: Vec<(String, u8)> = pins
+                        .iter()
+                        .map(|(_, pin)| (pin.to_string(), lamps_on as u8))
+                        .collect();
+                    let mut locked_io = io.lock().unwrap();
+                    locked_io.write_outputs(&outputs);
+                    *thread_lit_since.lock().unwrap() = lamps_on.then(Instant::now);
+                }
+                let phase_time = if lamps_on { on_time } else { off_time };
+                match stop_receiver.recv_timeout(phase_time) {
+                    Err(RecvTimeoutError::Timeout) => lamps_on = !lamps_on,
+                    _ => break,
+                }
+            }
+        });
+
+        Self {
+            flashing,
+            lit_since,
+            stop_sender,
+            handle,
+        }
+    }
+
+    pub fn is_flashing(&self, index: usize) -> bool {
+        self.flashing.contains(&index)
+    }
+
+    // false while the given lamp is flashing and in (or just leaving) its off phase
+    pub fn is_lit(&self, index: usize) -> bool {
+        if !self.flashing.contains(&index) {
+            return true;
+        }
+        match *self.lit_since.lock().unwrap() {
+            Some(since) => since.elapsed() >= Duration::from_millis(BLINK_SETTLE_MS),
+            None => false,
+        }
+    }
+
+    // blocks until the blink thread has finished its last write
+    pub fn stop(self) {
+        let _ = self.stop_sender.send(());
+        let _ = self.handle.join();
+    }
+}
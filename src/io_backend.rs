@@ -0,0 +1,344 @@
+use crate::io_config::{aspect_output_pins, IOBackendType, PinConfig};
+use crate::simulator::SignalSimulator;
+use picontrol::bindings::SPIValue;
+use picontrol::PiControl;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+
+// unknown pins are not written and read as 0, i.e. as a dark lamp
+fn pin_not_found(pin: &str) {
+    eprintln!("PIN {} NOT FOUND IN THE PROCESS IMAGE", pin);
+}
+
+// address of a single bit in the process image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PinAddress {
+    pub address: u16,
+    pub bit: u8,
+}
+
+// hardware access used by the OC, so the signal logic does not depend on a RevPi
+pub trait IOBackend: Send + Sync {
+    fn resolve_pin(&mut self, pin: &str) -> Option<PinAddress>;
+    fn write_output(&mut self, pin: &str, value: u8);
+    fn read_input(&mut self, pin: &str) -> u8;
+    fn read_image(&mut self, address: u16, length: usize) -> Vec<u8>;
+    // sets all given pins in one process image update, so no intermediate state is visible
+    fn write_outputs(&mut self, values: &[(String, u8)]);
+}
+
+// the backend is shared between the OC and its blink driver
+pub type SharedIOBackend = Arc<Mutex<dyn IOBackend>>;
+
+// accesses the RevPi process image through /dev/piControl0, the device stays open for the
+// lifetime of the OC and pin names are only looked up once
+pub struct PiControlBackend {
+    pc: PiControl,
+    variables: HashMap<String, PinAddress>,
+}
+
+impl PiControlBackend {
+    pub fn new() -> Self {
+        let pc = PiControl::new().unwrap_or_else(|e| {
+            eprintln!("COULD NOT OPEN PICONTROL DEVICE: {:?}", e);
+            std::process::exit(1);
+        });
+        Self {
+            pc,
+            variables: HashMap::new(),
+        }
+    }
+}
+
+impl IOBackend for PiControlBackend {
+    fn resolve_pin(&mut self, pin: &str) -> Option<PinAddress> {
+        if let Some(pin_address) = self.variables.get(pin) {
+            return Some(*pin_address);
+        }
+        // piControl leaves the variable empty if no variable has this name, every found
+        // variable has a length
+        let var_data = self.pc.find_variable(pin);
+        if var_data.i16uLength == 0 {
+            return None;
+        }
+        let pin_address = PinAddress {
+            address: var_data.i16uAddress,
+            bit: var_data.i8uBit,
+        };
+        self.variables.insert(pin.to_string(), pin_address);
+        Some(pin_address)
+    }
+
+    fn write_output(&mut self, pin: &str, value: u8) {
+        let pin_address = match self.resolve_pin(pin) {
+            Some(pin_address) => pin_address,
+            None => return pin_not_found(pin),
+        };
+        let mut val = SPIValue {
+            i16uAddress: pin_address.address,
+            i8uBit: pin_address.bit,
+            i8uValue: value,
+        };
+        self.pc.set_bit_value(&mut val);
+    }
+
+    fn read_input(&mut self, pin: &str) -> u8 {
+        let pin_address = match self.resolve_pin(pin) {
+            Some(pin_address) => pin_address,
+            None => {
+                pin_not_found(pin);
+                return 0;
+            }
+        };
+        let res = self.pc.read(pin_address.address.into(), 1);
+        (res[0] >> pin_address.bit) & 1
+    }
+
+    fn read_image(&mut self, address: u16, length: usize) -> Vec<u8> {
+        self.pc.read(address.into(), length)
+    }
+
+    // reads the bytes holding the pins, changes their bits and writes them back, bytes next to
+    // each other at once, so no byte without one of the pins (e.g. of another module) is written
+    fn write_outputs(&mut self, values: &[(String, u8)]) {
+        let mut pin_values: Vec<(PinAddress, u8)> = values
+            .iter()
+            .filter_map(|(pin, value)| match self.resolve_pin(pin) {
+                Some(pin_address) => Some((pin_address, *value)),
+                None => {
+                    pin_not_found(pin);
+                    None
+                }
+            })
+            .collect();
+        // the sort is stable, so the last value given for a pin is written
+        pin_values.sort_by_key(|(pin_address, _)| pin_address.address);
+        for run in byte_runs(&pin_values) {
+            let start = run[0].0.address;
+            let end = run[run.len() - 1].0.address;
+            let mut image = self.pc.read(start.into(), (end - start) as usize + 1);
+            for (pin_address, value) in run {
+                set_bit(
+                    &mut image,
+                    (pin_address.address - start) as usize,
+                    pin_address.bit,
+                    *value,
+                );
+            }
+            self.pc.write(start.into(), &image);
+        }
+    }
+}
+
+// splits pins sorted by address into runs of pins in the same or in adjacent bytes
+fn byte_runs(pin_values: &[(PinAddress, u8)]) -> Vec<&[(PinAddress, u8)]> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for index in 1..=pin_values.len() {
+        if index == pin_values.len()
+            || pin_values[index].0.address > pin_values[index - 1].0.address + 1
+        {
+            runs.push(&pin_values[start..index]);
+            start = index;
+        }
+    }
+    runs
+}
+
+// write_outputs updates each run of adjacent bytes separately, so an aspect whose outputs lie on
+// bytes apart (e.g. on different modules) is not written in a single update
+pub fn warn_split_aspect_writes(io: &mut dyn IOBackend, cfg: &PinConfig) {
+    let mut pin_values: Vec<(PinAddress, u8)> = aspect_output_pins(cfg)
+        .filter_map(|pin| io.resolve_pin(pin))
+        .map(|pin_address| (pin_address, 0))
+        .collect();
+    pin_values.sort_by_key(|(pin_address, _)| pin_address.address);
+    let writes = byte_runs(&pin_values).len();
+    if writes > 1 {
+        println!(
+            "WARNING: THE LAMP AND INDICATOR OUTPUTS LIE IN {} SEPARATE BYTE RANGES OF THE PROCESS IMAGE, AN ASPECT CHANGE IS WRITTEN IN {} STEPS",
+            writes, writes
+        );
+    }
+}
+
+// the simulated process image holds the 14 inputs (I_1 to I_14, bytes 0 and 1) and the 14
+// outputs (O_1 to O_14, bytes 2 and 3) of a RevPi DIO
+const SIMULATED_PINS: usize = 14;
+
+fn simulated_variables() -> HashMap<String, PinAddress> {
+    let mut variables = HashMap::new();
+    for (prefix, first_address) in [("I", 0), ("O", 2)] {
+        for index in 0..SIMULATED_PINS {
+            let pin_address = PinAddress {
+                address: first_address + (index / 8) as u16,
+                bit: (index % 8) as u8,
+            };
+            variables.insert(format!("{}_{}", prefix, index + 1), pin_address);
+        }
+    }
+    variables
+}
+
+// in-memory process image, the inputs are driven by the simulated lamps
+pub struct SimulatedBackend {
+    variables: HashMap<String, PinAddress>,
+    image: Vec<u8>,
+    simulator: SignalSimulator,
+}
+
+impl SimulatedBackend {
+    // the fault injection is only started by start_fault_injection, so looking up pins has no
+    // side effects
+    pub fn new(cfg: &PinConfig) -> Self {
+        Self {
+            variables: simulated_variables(),
+            image: vec![0; 4],
+            simulator: SignalSimulator::new(cfg),
+        }
+    }
+
+    pub fn start_fault_injection(&self, cfg: &PinConfig) {
+        self.simulator.start_fault_injection(cfg);
+    }
+
+    fn read_bit(&mut self, pin: &str) -> u8 {
+        match self.resolve_pin(pin) {
+            Some(pin_address) => bit_value(&self.image, pin_address),
+            None => {
+                pin_not_found(pin);
+                0
+            }
+        }
+    }
+}
+
+fn bit_value(image: &[u8], pin_address: PinAddress) -> u8 {
+    (image[pin_address.address as usize] >> pin_address.bit) & 1
+}
+
+fn set_bit(image: &mut [u8], offset: usize, bit: u8, value: u8) {
+    if value == 0 {
+        image[offset] &= !(1 << bit);
+    } else {
+        image[offset] |= 1 << bit;
+    }
+}
+
+impl IOBackend for SimulatedBackend {
+    fn resolve_pin(&mut self, pin: &str) -> Option<PinAddress> {
+        self.variables.get(pin).copied()
+    }
+
+    fn write_output(&mut self, pin: &str, value: u8) {
+        let pin_address = match self.resolve_pin(pin) {
+            Some(pin_address) => pin_address,
+            None => {
+                pin_not_found(pin);
+                return;
+            }
+        };
+        set_bit(
+            &mut self.image,
+            pin_address.address as usize,
+            pin_address.bit,
+            value,
+        );
+    }
+
+    fn read_input(&mut self, pin: &str) -> u8 {
+        let variables = &self.variables;
+        let image = &self.image;
+        let feedback = self
+            .simulator
+            .feedback(pin, |output| match variables.get(output) {
+                Some(pin_address) => bit_value(image, *pin_address),
+                None => 0,
+            });
+        match feedback {
+            Some(value) => value,
+            None => self.read_bit(pin),
+        }
+    }
+
+    fn read_image(&mut self, address: u16, length: usize) -> Vec<u8> {
+        // the inputs of the simulated lamps are only evaluated on access
+        for input in self.simulator.inputs() {
+            let value = self.read_input(&input);
+            self.write_output(&input, value);
+        }
+        let start = address as usize;
+        if self.image.len() < start + length {
+            self.image.resize(start + length, 0);
+        }
+        self.image[start..start + length].to_vec()
+    }
+
+    // the lamps are only evaluated while the backend is locked, so this is already atomic
+    fn write_outputs(&mut self, values: &[(String, u8)]) {
+        for (pin, value) in values {
+            self.write_output(pin, *value);
+        }
+    }
+}
+
+// the backend given on the command line overrides the one from the pin config
+pub fn backend_type(cfg: &PinConfig, backend_arg_pos: usize) -> IOBackendType {
+    match env::args().nth(backend_arg_pos) {
+        Some(arg) => arg.parse::<IOBackendType>().unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }),
+        None => cfg.backend,
+    }
+}
+
+pub fn get_backend(cfg: &PinConfig, backend_arg_pos: usize) -> SharedIOBackend {
+    let backend_type = backend_type(cfg, backend_arg_pos);
+    println!("IO BACKEND {:?}", backend_type);
+    match backend_type {
+        IOBackendType::PiControl => Arc::new(Mutex::new(PiControlBackend::new())),
+        IOBackendType::Simulated => {
+            let backend = SimulatedBackend::new(cfg);
+            backend.start_fault_injection(cfg);
+            Arc::new(Mutex::new(backend))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin_value(address: u16, bit: u8) -> (PinAddress, u8) {
+        (PinAddress { address, bit }, 1)
+    }
+
+    fn addresses(runs: Vec<&[(PinAddress, u8)]>) -> Vec<Vec<u16>> {
+        runs.iter()
+            .map(|run| {
+                run.iter()
+                    .map(|(pin_address, _)| pin_address.address)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn byte_runs_keep_adjacent_bytes_together() {
+        let pin_values = [pin_value(70, 0), pin_value(70, 5), pin_value(71, 2)];
+        assert_eq!(addresses(byte_runs(&pin_values)), vec![vec![70, 70, 71]]);
+    }
+
+    #[test]
+    fn byte_runs_skip_bytes_without_pins() {
+        // primary and backup outputs on two modules, the inputs of the second module lie between
+        let pin_values = [pin_value(70, 0), pin_value(71, 1), pin_value(159, 0)];
+        assert_eq!(
+            addresses(byte_runs(&pin_values)),
+            vec![vec![70, 71], vec![159]]
+        );
+        assert!(byte_runs(&[]).is_empty());
+    }
+}
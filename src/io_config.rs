@@ -1,17 +1,296 @@
 use config_file::FromConfigFile;
+use sci_rs::scils::{SCILSMain, SCILSZs2, SCILSZs3};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
+use std::fs;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IOBackendType {
+    #[default]
+    PiControl,
+    Simulated,
+}
+
+impl FromStr for IOBackendType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "picontrol" => Ok(IOBackendType::PiControl),
+            "simulated" => Ok(IOBackendType::Simulated),
+            _ => Err(format!(
+                "UNKNOWN IO BACKEND {}, EXPECTED picontrol OR simulated",
+                s
+            )),
+        }
+    }
+}
+
+// fault injection for the simulated backend
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SimulatorConfig {
+    pub(crate) control_socket: Option<String>,
+    pub(crate) fault_script: Option<String>,
+}
+
+// longest transition, the OC waits for it while handling the telegram and holding the OC lock
+pub const MAX_TRANSITION_MS: u64 = 1000;
+
+// time a flashing lamp needs after switching on until its input reports it as lit
+pub const BLINK_SETTLE_MS: u64 = 50;
+
+pub const SIGNAL_ASPECTS: [SCILSMain; 17] = [
+    SCILSMain::Hp0,
+    SCILSMain::Hp0PlusSh1,
+    SCILSMain::Hp0WithDrivingIndicator,
+    SCILSMain::Ks1,
+    SCILSMain::Ks1Flashing,
+    SCILSMain::Ks1FlashingWithAdditionalLight,
+    SCILSMain::Ks2,
+    SCILSMain::Ks2WithAdditionalLight,
+    SCILSMain::Sh1,
+    SCILSMain::IdLight,
+    SCILSMain::Hp0Hv,
+    SCILSMain::Hp1,
+    SCILSMain::Hp2,
+    SCILSMain::Vr0,
+    SCILSMain::Vr1,
+    SCILSMain::Vr2,
+    SCILSMain::Off,
+];
+
+// pins of the Zs3, Zs3v, Zs2 and Zs2v indicators
+pub fn indicator_pins(cfg: &PinConfig) -> impl Iterator<Item = &String> {
+    [&cfg.zs3, &cfg.zs3v, &cfg.zs2, &cfg.zs2v]
+        .into_iter()
+        .flatten()
+        .flat_map(|indicator_cfg| indicator_cfg.pins.iter())
+}
+
+// pins written together when an aspect is shown: the lamps on both lines and the indicators
+pub fn aspect_output_pins(cfg: &PinConfig) -> impl Iterator<Item = &String> {
+    cfg.pins_output
+        .iter()
+        .chain(cfg.pins_output_backup.iter())
+        .chain(indicator_pins(cfg))
+}
+
+// key of the given aspect in the signals of the pin config
+pub fn signal_name(main: SCILSMain) -> &'static str {
+    match main {
+        SCILSMain::Hp0 => "Hp0",
+        SCILSMain::Hp0PlusSh1 => "Hp0PlusSh1",
+        SCILSMain::Hp0WithDrivingIndicator => "Hp0WithDrivingIndicator",
+        SCILSMain::Ks1 => "Ks1",
+        SCILSMain::Ks1Flashing => "Ks1Flashing",
+        SCILSMain::Ks1FlashingWithAdditionalLight => "Ks1FlashingWithAdditionalLight",
+        SCILSMain::Ks2 => "Ks2",
+        SCILSMain::Ks2WithAdditionalLight => "Ks2WithAdditionalLight",
+        SCILSMain::Sh1 => "Sh1",
+        SCILSMain::IdLight => "IdLight",
+        SCILSMain::Hp0Hv => "Hp0Hv",
+        SCILSMain::Hp1 => "Hp1",
+        SCILSMain::Hp2 => "Hp2",
+        SCILSMain::Vr0 => "Vr0",
+        SCILSMain::Vr1 => "Vr1",
+        SCILSMain::Vr2 => "Vr2",
+        SCILSMain::Off => "Off",
+    }
+}
+
+pub fn signal_from_name(name: &str) -> Option<SCILSMain> {
+    SIGNAL_ASPECTS
+        .iter()
+        .copied()
+        .find(|main| signal_name(*main) == name)
+}
+
+// reaction to a lamp whose input is lit although the lamp should be dark
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnexpectedLampReaction {
+    MostRestrictive, // show the most restrictive aspect
+    Report,          // send a signal aspect status to the interlocking
+    CutPower,        // switch the master pin off
+}
+
+fn default_unexpected_lamp_reactions() -> Vec<UnexpectedLampReaction> {
+    vec![
+        UnexpectedLampReaction::MostRestrictive,
+        UnexpectedLampReaction::Report,
+    ]
+}
+
+fn default_most_restrictive_aspect() -> String {
+    "Ks2".to_string()
+}
+
+fn default_frequency() -> f64 {
+    1.0
+}
+
+fn default_duty_cycle() -> f64 {
+    0.5
+}
+
+// lamps of a flashing aspect, pins marks the lamps (1) that blink at the given frequency (Hz)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FlashingConfig {
+    pub(crate) pins: Vec<u8>,
+    #[serde(default = "default_frequency")]
+    pub(crate) frequency: f64,
+    #[serde(default = "default_duty_cycle")]
+    pub(crate) duty_cycle: f64,
+}
+
+// how the lamps change from one aspect to the next
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionMode {
+    #[default]
+    Direct, // the new pattern is written at once
+    RestrictiveFirst, // lamps of the most restrictive aspect on, old lamps off, then new lamps on
+}
+
+fn default_dwell_time() -> u64 {
+    100
+}
+
+// lamp patterns keyed by the parsed sci-rs aspect, keys that are no signal name are kept apart,
+// so validate_config can report them with their line
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(from = "BTreeMap<String, Vec<u8>>", into = "BTreeMap<String, Vec<u8>>")]
+pub struct SignalPatterns {
+    patterns: Vec<(SCILSMain, Vec<u8>)>,
+    unknown: BTreeMap<String, Vec<u8>>,
+}
+
+impl SignalPatterns {
+    pub fn get(&self, main: SCILSMain) -> Option<&Vec<u8>> {
+        self.patterns
+            .iter()
+            .find(|(known, _)| *known == main)
+            .map(|(_, pattern)| pattern)
+    }
+
+    pub fn contains(&self, main: SCILSMain) -> bool {
+        self.get(main).is_some()
+    }
+
+    // pattern of the aspect with the given name, None for unknown names
+    pub fn get_by_name(&self, name: &str) -> Option<&Vec<u8>> {
+        self.get(signal_from_name(name)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SCILSMain, &Vec<u8>)> {
+        self.patterns.iter().map(|(main, pattern)| (*main, pattern))
+    }
+
+    pub fn insert(&mut self, main: SCILSMain, pattern: Vec<u8>) {
+        match self.patterns.iter_mut().find(|(known, _)| *known == main) {
+            Some((_, known_pattern)) => *known_pattern = pattern,
+            None => self.patterns.push((main, pattern)),
+        }
+    }
+}
+
+impl From<BTreeMap<String, Vec<u8>>> for SignalPatterns {
+    fn from(signals: BTreeMap<String, Vec<u8>>) -> Self {
+        let mut patterns = SignalPatterns::default();
+        for (name, pattern) in signals {
+            match signal_from_name(&name) {
+                Some(main) => patterns.insert(main, pattern),
+                None => {
+                    patterns.unknown.insert(name, pattern);
+                }
+            }
+        }
+        patterns
+    }
+}
+
+impl From<SignalPatterns> for BTreeMap<String, Vec<u8>> {
+    fn from(patterns: SignalPatterns) -> Self {
+        let mut signals = patterns.unknown;
+        for (main, pattern) in patterns.patterns {
+            signals.insert(signal_name(main).to_string(), pattern);
+        }
+        signals
+    }
+}
+
+// transition between two aspects ("*" matches every aspect), the intermediate patterns are
+// shown in order for dwell_time milliseconds each, otherwise mode (or transition_mode) applies
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransitionConfig {
+    pub(crate) from: String,
+    pub(crate) to: String,
+    pub(crate) mode: Option<TransitionMode>,
+    #[serde(default)]
+    pub(crate) intermediate: Vec<Vec<u8>>,
+    #[serde(default = "default_dwell_time")]
+    pub(crate) dwell_time: u64,
+}
+
+// output pins of an indicator (Zs3, Zs3v, Zs2, Zs2v) and a pattern per displayed value,
+// keyed like the sci-rs values, e.g. "Index4" for Zs3 or "LetterA" for Zs2
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndicatorConfig {
+    pub(crate) pins: Vec<String>,
+    pub(crate) signals: BTreeMap<String, Vec<u8>>,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PinConfig {
+    #[serde(default)]
+    pub(crate) backend: IOBackendType,
     number_of_pins: usize,
     pub(crate) pins_output: Vec<String>,
     pub(crate) pins_output_backup: Vec<String>,
     pub(crate) pins_input: Vec<String>,
     pub(crate) day_night_pin: String,
-    pub(crate) signals: HashMap<String, Vec<u8>>,
+    // shown at start-up, on connection loss and instead of aspects without a configured pattern
+    #[serde(default = "default_most_restrictive_aspect")]
+    pub(crate) most_restrictive_aspect: String,
+    pub(crate) signals: SignalPatterns,
+    #[serde(default)]
+    pub(crate) flashing: BTreeMap<String, FlashingConfig>,
+    pub(crate) zs3: Option<IndicatorConfig>,
+    pub(crate) zs3v: Option<IndicatorConfig>,
+    pub(crate) zs2: Option<IndicatorConfig>,
+    pub(crate) zs2v: Option<IndicatorConfig>,
+    // switch all lamps off when a signal aspect requests dark switching
+    #[serde(default)]
+    pub(crate) dark_switching: bool,
+    // seconds between automatic re-tests of failed primary lines, disabled if not set
+    pub(crate) primary_retest_interval: Option<u64>,
+    // output switching the power of all lamps, on while the OC is running
+    pub(crate) master_pin: Option<String>,
+    #[serde(default = "default_unexpected_lamp_reactions")]
+    pub(crate) unexpected_lamp_reactions: Vec<UnexpectedLampReaction>,
+    // report the lamp masks in the first three bytes of the nationally specified information of
+    // the signal aspect status instead of echoing the bytes commanded by the interlocking
+    #[serde(default)]
+    pub(crate) lamp_status_bytes: bool,
+    // aspects shown instead of an aspect with a lamp failed on both lines, tried in order,
+    // "Off" switches the signal dark
+    #[serde(default)]
+    pub(crate) degraded_aspects: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub(crate) transition_mode: TransitionMode,
+    // milliseconds between the steps of a restrictive_first transition
+    #[serde(default = "default_dwell_time")]
+    pub(crate) transition_dwell_time: u64,
+    #[serde(default)]
+    pub(crate) transitions: Vec<TransitionConfig>,
+    #[serde(default)]
+    pub(crate) simulator: SimulatorConfig,
 }
 
 impl Default for PinConfig {
@@ -21,6 +300,7 @@ impl Default for PinConfig {
         let ks2_pins: Vec<u8> = vec![0, 0, 1, 1];
 
         Self {
+            backend: IOBackendType::PiControl,
             number_of_pins: 4,
             pins_output: vec![
                 "O_1".to_string(),
@@ -41,20 +321,684 @@ impl Default for PinConfig {
                 "O_4".to_string(),
             ],
             day_night_pin: "O_7".to_string(),
-            signals: HashMap::from([
+            most_restrictive_aspect: default_most_restrictive_aspect(),
+            signals: SignalPatterns::from(BTreeMap::from([
                 ("Off".to_string(), off_pins),
                 ("Ks1".to_string(), ks1_pins),
                 ("Ks2".to_string(), ks2_pins),
-            ]),
+            ])),
+            flashing: BTreeMap::new(),
+            zs3: None,
+            zs3v: None,
+            zs2: None,
+            zs2v: None,
+            dark_switching: false,
+            primary_retest_interval: None,
+            master_pin: None,
+            unexpected_lamp_reactions: default_unexpected_lamp_reactions(),
+            lamp_status_bytes: false,
+            degraded_aspects: BTreeMap::new(),
+            transition_mode: TransitionMode::Direct,
+            transition_dwell_time: default_dwell_time(),
+            transitions: Vec::new(),
+            simulator: SimulatorConfig::default(),
+        }
+    }
+}
+
+// a problem found in the pin config and the line of the config file it refers to
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    pub(crate) line: Option<usize>,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// part of the config file a problem belongs to
+enum Section {
+    // the keys above the first table
+    Top,
+    // [name]
+    Table(String),
+    // the index-th [[name]], counted from 0
+    ArrayTable(String, usize),
+}
+
+fn table(name: &str) -> Section {
+    Section::Table(name.to_string())
+}
+
+// name of the table opened by the line, e.g. "zs3" for [zs3] and "transitions" for [[transitions]]
+fn table_header(line: &str) -> Option<&str> {
+    let line = line.trim();
+    line.strip_prefix("[[")
+        .and_then(|line| line.strip_suffix("]]"))
+        .or_else(|| {
+            line.strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+        })
+        .map(|name| name.trim())
+}
+
+// first line (starting at 1) of the section that sets the given key, opens it as a table or
+// contains it as a quoted string, the header of the section if no line does, comments are ignored
+fn find_line(text: &str, section: &Section, key: &str) -> Option<usize> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim_start())
+        .collect();
+    let is_header = |line: &str, name: &str, array: bool| {
+        line.starts_with("[[") == array && table_header(line) == Some(name)
+    };
+    let start = match section {
+        Section::Top => 0,
+        Section::Table(name) => lines.iter().position(|line| is_header(line, name, false))? + 1,
+        Section::ArrayTable(name, index) => {
+            lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| is_header(line, name, true))
+                .nth(*index)?
+                .0
+                + 1
         }
+    };
+    let end = lines[start..]
+        .iter()
+        .position(|line| table_header(line).is_some())
+        .map_or(lines.len(), |length| start + length);
+    let found = lines[start..end].iter().position(|line| {
+        line.contains(&format!("\"{}\"", key))
+            || line.starts_with(&format!("{}=", key))
+            || line.starts_with(&format!("{} =", key))
+    });
+    match (found, section) {
+        (Some(index), _) => Some(start + index + 1),
+        // a top-level key may also be written as a table
+        (None, Section::Top) => lines
+            .iter()
+            .position(|line| table_header(line) == Some(key))
+            .map(|index| index + 1),
+        (None, _) => Some(start),
     }
 }
 
-pub fn get_config(config_arg_pos: usize) -> PinConfig {
+// collects the problems of the pin config, text is the content of the config file
+struct Validator<'a> {
+    text: &'a str,
+    problems: Vec<ConfigProblem>,
+}
+
+impl Validator<'_> {
+    fn report(&mut self, section: &Section, key: &str, message: String) {
+        self.problems.push(ConfigProblem {
+            line: find_line(self.text, section, key),
+            message,
+        });
+    }
+
+    // aspect names are the sci-rs names of SCILSMain, e.g. "Ks1", line_key locates the name in
+    // the section
+    fn check_signal_name(
+        &mut self,
+        section: &Section,
+        line_key: &str,
+        key: &str,
+        name: &str,
+    ) -> Option<SCILSMain> {
+        let main = signal_from_name(name);
+        if main.is_none() {
+            let suggestion = SIGNAL_ASPECTS
+                .iter()
+                .map(|main| signal_name(*main))
+                .find(|known| known.eq_ignore_ascii_case(name));
+            let message = match suggestion {
+                Some(known) => format!(
+                    "UNKNOWN SIGNAL {} IN {}, DID YOU MEAN {}?",
+                    name, key, known
+                ),
+                None => format!("UNKNOWN SIGNAL {} IN {}", name, key),
+            };
+            self.report(section, line_key, message);
+        }
+        main
+    }
+
+    // indicator values are the sci-rs value names, e.g. "Index4" for Zs3
+    fn check_indicator_value(&mut self, key: &str, name: &str, values: &[String]) {
+        if values.iter().any(|value| value == name) {
+            return;
+        }
+        let message = match values.iter().find(|value| value.eq_ignore_ascii_case(name)) {
+            Some(value) => format!("UNKNOWN VALUE {} IN {}, DID YOU MEAN {}?", name, key, value),
+            None => format!("UNKNOWN VALUE {} IN {}", name, key),
+        };
+        self.report(&table(key), name, message);
+    }
+
+    // line_key locates the pattern in the section
+    fn check_pattern(
+        &mut self,
+        section: &Section,
+        line_key: &str,
+        key: &str,
+        name: &str,
+        pattern: &[u8],
+        length: usize,
+    ) {
+        if pattern.len() != length {
+            self.report(
+                section,
+                line_key,
+                format!(
+                    "PATTERN OF {} IN {} HAS {} VALUES, EXPECTED {}",
+                    name,
+                    key,
+                    pattern.len(),
+                    length
+                ),
+            );
+        }
+        if pattern.iter().any(|value| *value > 1) {
+            self.report(
+                section,
+                line_key,
+                format!("PATTERN OF {} IN {} MAY ONLY CONTAIN 0 AND 1", name, key),
+            );
+        }
+    }
+}
+
+// names of all values of an indicator, as shown with show_indicator
+fn indicator_values<T: TryFrom<u8> + fmt::Debug>() -> Vec<String> {
+    (0..=u8::MAX)
+        .filter_map(|byte| T::try_from(byte).ok())
+        .map(|value| format!("{:?}", value))
+        .collect()
+}
+
+// lamps an intermediate pattern lights although they are dark in the old, the new and the most
+// restrictive aspect, such a pattern could display a more permissive aspect than both
+fn foreign_lamps(cfg: &PinConfig, pattern: &[u8], from: &str, to: &str) -> Vec<usize> {
+    let lit = |name: &str, lamp: usize| {
+        cfg.signals
+            .get_by_name(name)
+            .is_some_and(|aspect_pattern| aspect_pattern.get(lamp) == Some(&1))
+    };
+    pattern
+        .iter()
+        .enumerate()
+        .filter(|(lamp, value)| {
+            **value == 1
+                && !lit(from, *lamp)
+                && !lit(to, *lamp)
+                && !lit(&cfg.most_restrictive_aspect, *lamp)
+        })
+        .map(|(lamp, _)| lamp)
+        .collect()
+}
+
+// checks everything the OC relies on at runtime, so a broken config is found at start-up
+pub fn validate_config(cfg: &PinConfig, text: &str) -> Vec<ConfigProblem> {
+    let mut validator = Validator {
+        text,
+        problems: Vec::new(),
+    };
+    for (key, pins) in [
+        ("pins_output", &cfg.pins_output),
+        ("pins_output_backup", &cfg.pins_output_backup),
+        ("pins_input", &cfg.pins_input),
+    ] {
+        if pins.len() != cfg.number_of_pins {
+            validator.report(
+                &Section::Top,
+                key,
+                format!(
+                    "{} HAS {} PINS, BUT number_of_pins IS {}",
+                    key,
+                    pins.len(),
+                    cfg.number_of_pins
+                ),
+            );
+        }
+    }
+
+    // a pin has one role, e.g. a lamp output must not also switch an indicator
+    let mut pin_uses: Vec<(&str, &String)> = Vec::new();
+    for (key, pins) in [
+        ("pins_output", &cfg.pins_output),
+        ("pins_output_backup", &cfg.pins_output_backup),
+        ("pins_input", &cfg.pins_input),
+    ] {
+        pin_uses.extend(pins.iter().map(|pin| (key, pin)));
+    }
+    pin_uses.push(("day_night_pin", &cfg.day_night_pin));
+    pin_uses.extend(cfg.master_pin.iter().map(|pin| ("master_pin", pin)));
+    let indicator_keys = ["zs3", "zs3v", "zs2", "zs2v"];
+    for (key, indicator_cfg) in [
+        ("zs3", &cfg.zs3),
+        ("zs3v", &cfg.zs3v),
+        ("zs2", &cfg.zs2),
+        ("zs2v", &cfg.zs2v),
+    ] {
+        if let Some(indicator_cfg) = indicator_cfg {
+            pin_uses.extend(indicator_cfg.pins.iter().map(|pin| (key, pin)));
+        }
+    }
+    let mut pin_keys: BTreeMap<&String, Vec<&str>> = BTreeMap::new();
+    for (key, pin) in pin_uses {
+        pin_keys.entry(pin).or_default().push(key);
+    }
+    for (pin, keys) in pin_keys.iter().filter(|(_, keys)| keys.len() > 1) {
+        let key = keys[keys.len() - 1];
+        let (section, line_key) = match indicator_keys.contains(&key) {
+            true => (table(key), "pins"),
+            false => (Section::Top, key),
+        };
+        validator.report(
+            &section,
+            line_key,
+            format!("PIN {} IS USED MORE THAN ONCE: {}", pin, keys.join(", ")),
+        );
+    }
+
+    for (name, pattern) in cfg.signals.unknown.iter() {
+        validator.check_signal_name(&Section::Top, "signals", "signals", name);
+        validator.check_pattern(
+            &Section::Top,
+            "signals",
+            "signals",
+            name,
+            pattern,
+            cfg.number_of_pins,
+        );
+    }
+    for (main, pattern) in cfg.signals.iter() {
+        let name = signal_name(main);
+        validator.check_pattern(
+            &Section::Top,
+            "signals",
+            "signals",
+            name,
+            pattern,
+            cfg.number_of_pins,
+        );
+    }
+    if validator
+        .check_signal_name(
+            &Section::Top,
+            "most_restrictive_aspect",
+            "most_restrictive_aspect",
+            &cfg.most_restrictive_aspect,
+        )
+        .is_some_and(|main| !cfg.signals.contains(main))
+    {
+        validator.report(
+            &Section::Top,
+            "most_restrictive_aspect",
+            format!(
+                "MOST RESTRICTIVE SIGNAL {} HAS NO PATTERN IN signals",
+                cfg.most_restrictive_aspect
+            ),
+        );
+    }
+
+    for (name, flashing) in cfg.flashing.iter() {
+        let section = table(&format!("flashing.{}", name));
+        validator.check_signal_name(&section, name, "flashing", name);
+        if cfg.signals.get_by_name(name).is_none() {
+            validator.report(
+                &section,
+                name,
+                format!("FLASHING SIGNAL {} HAS NO PATTERN IN signals", name),
+            );
+        }
+        validator.check_pattern(
+            &section,
+            "pins",
+            "flashing",
+            name,
+            &flashing.pins,
+            cfg.number_of_pins,
+        );
+        // the blink driver derives its period from the frequency
+        let period_valid = flashing.frequency.is_finite()
+            && flashing.frequency > 0.0
+            && Duration::try_from_secs_f64(1.0 / flashing.frequency).is_ok();
+        if !period_valid {
+            validator.report(
+                &section,
+                "frequency",
+                format!(
+                    "FREQUENCY {} OF FLASHING SIGNAL {} MUST BE A POSITIVE NUMBER OF HZ",
+                    flashing.frequency, name
+                ),
+            );
+        }
+        let duty_cycle_valid = flashing.duty_cycle > 0.0 && flashing.duty_cycle <= 1.0;
+        if !duty_cycle_valid {
+            validator.report(
+                &section,
+                "duty_cycle",
+                format!(
+                    "DUTY CYCLE {} OF FLASHING SIGNAL {} MUST BE ABOVE 0 AND AT MOST 1",
+                    flashing.duty_cycle, name
+                ),
+            );
+        }
+        // the lamp check skips a flashing lamp until it has been on for BLINK_SETTLE_MS
+        let on_time_ms = 1000.0 / flashing.frequency * flashing.duty_cycle;
+        if period_valid && duty_cycle_valid && on_time_ms <= BLINK_SETTLE_MS as f64 {
+            validator.report(
+                &section,
+                "duty_cycle",
+                format!(
+                    "ON PHASE OF {:.1} MS OF FLASHING SIGNAL {} MUST BE LONGER THAN {} MS TO BE CHECKED",
+                    on_time_ms, name, BLINK_SETTLE_MS
+                ),
+            );
+        }
+    }
+
+    let section = table("degraded_aspects");
+    for (name, substitutes) in cfg.degraded_aspects.iter() {
+        validator.check_signal_name(&section, name, "degraded_aspects", name);
+        for substitute in substitutes {
+            if validator
+                .check_signal_name(&section, substitute, "degraded_aspects", substitute)
+                .is_some_and(|main| main != SCILSMain::Off && !cfg.signals.contains(main))
+            {
+                validator.report(
+                    &section,
+                    substitute,
+                    format!(
+                        "SUBSTITUTE {} FOR {} HAS NO PATTERN IN signals",
+                        substitute, name
+                    ),
+                );
+            }
+        }
+    }
+
+    // restrictive_first waits twice at most
+    if 2 * cfg.transition_dwell_time > MAX_TRANSITION_MS {
+        validator.report(
+            &Section::Top,
+            "transition_dwell_time",
+            format!(
+                "transition_dwell_time {} MS MAKES A TRANSITION LONGER THAN {} MS",
+                cfg.transition_dwell_time, MAX_TRANSITION_MS
+            ),
+        );
+    }
+    for (index, transition) in cfg.transitions.iter().enumerate() {
+        let section = Section::ArrayTable("transitions".to_string(), index);
+        let steps = match transition.intermediate.len() {
+            0 => 2,
+            steps => steps as u64,
+        };
+        if steps.saturating_mul(transition.dwell_time) > MAX_TRANSITION_MS {
+            validator.report(
+                &section,
+                "dwell_time",
+                format!(
+                    "TRANSITION {} -> {} WAITS {} TIMES {} MS, AT MOST {} MS ARE ALLOWED",
+                    transition.from, transition.to, steps, transition.dwell_time, MAX_TRANSITION_MS
+                ),
+            );
+        }
+        for name in [&transition.from, &transition.to] {
+            if name != "*" {
+                validator.check_signal_name(&section, name, "transitions", name);
+            }
+        }
+        for pattern in transition.intermediate.iter() {
+            let name = format!("{} -> {}", transition.from, transition.to);
+            validator.check_pattern(
+                &section,
+                "intermediate",
+                "transitions",
+                &name,
+                pattern,
+                cfg.number_of_pins,
+            );
+            // "*" stands for every aspect with a pattern, the rule may apply to each of them
+            let aspects = |name: &String| -> Vec<String> {
+                if name == "*" {
+                    cfg.signals
+                        .iter()
+                        .map(|(main, _)| signal_name(main).to_string())
+                        .collect()
+                } else {
+                    vec![name.to_string()]
+                }
+            };
+            let foreign = aspects(&transition.from).into_iter().find_map(|from| {
+                aspects(&transition.to).into_iter().find_map(|to| {
+                    let lamps = foreign_lamps(cfg, pattern, &from, &to);
+                    (!lamps.is_empty()).then_some((from.clone(), to, lamps))
+                })
+            });
+            if let Some((from, to, lamps)) = foreign {
+                validator.report(
+                    &section,
+                    "intermediate",
+                    format!(
+                        "INTERMEDIATE PATTERN {:?} OF {} LIGHTS LAMPS {:?} THAT ARE DARK IN {}, {} AND {}",
+                        pattern, name, lamps, from, to, cfg.most_restrictive_aspect
+                    ),
+                );
+            }
+        }
+    }
+
+    for (key, indicator_cfg) in [
+        ("zs3", &cfg.zs3),
+        ("zs3v", &cfg.zs3v),
+        ("zs2", &cfg.zs2),
+        ("zs2v", &cfg.zs2v),
+    ] {
+        if let Some(indicator_cfg) = indicator_cfg {
+            let values = match key {
+                "zs3" | "zs3v" => indicator_values::<SCILSZs3>(),
+                _ => indicator_values::<SCILSZs2>(),
+            };
+            for (name, pattern) in indicator_cfg.signals.iter() {
+                validator.check_indicator_value(key, name, &values);
+                validator.check_pattern(
+                    &table(key),
+                    name,
+                    key,
+                    name,
+                    pattern,
+                    indicator_cfg.pins.len(),
+                );
+            }
+        }
+    }
+
+    if cfg
+        .unexpected_lamp_reactions
+        .contains(&UnexpectedLampReaction::CutPower)
+        && cfg.master_pin.is_none()
+    {
+        validator.report(
+            &Section::Top,
+            "unexpected_lamp_reactions",
+            "REACTION cut_power IN unexpected_lamp_reactions NEEDS A master_pin".to_string(),
+        );
+    }
+    validator.problems
+}
+
+// flag given instead of the config file path to run on the pins of PinConfig::default()
+pub const DEFAULT_CONFIG_FLAG: &str = "--default-config";
+
+// reasons why the pin config cannot be used
+#[derive(Debug)]
+pub enum ConfigError {
+    NotFound(String),
+    Invalid(String, Vec<ConfigProblem>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::NotFound(path) => write!(
+                f,
+                "CONFIG FILE {} NOT FOUND, GIVE {} INSTEAD OF THE PATH TO USE THE DEFAULT PINS",
+                path, DEFAULT_CONFIG_FLAG
+            ),
+            ConfigError::Invalid(path, problems) => {
+                write!(f, "{} PROBLEMS FOUND IN {}", problems.len(), path)?;
+                for problem in problems.iter() {
+                    write!(f, "\n{}: {}", path, problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// version of the canonical config, to be raised whenever its format changes
+const CANONICAL_CONFIG_VERSION: u32 = 1;
+
+fn join<T: fmt::Display>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn canonical_indicator(
+    lines: &mut Vec<String>,
+    key: &str,
+    indicator_cfg: &Option<IndicatorConfig>,
+) {
+    if let Some(indicator_cfg) = indicator_cfg {
+        lines.push(format!("{}.pins={}", key, join(&indicator_cfg.pins)));
+        for (name, pattern) in indicator_cfg.signals.iter() {
+            lines.push(format!("{}.signals.{}={}", key, name, join(pattern)));
+        }
+    }
+}
+
+// every setting that decides what the signal shows, one per line in a fixed order, so equal
+// configs give equal checksums; backend and simulator only select the hardware and are left out
+pub fn canonical_config(cfg: &PinConfig) -> String {
+    let mut lines = vec![
+        format!("version={}", CANONICAL_CONFIG_VERSION),
+        format!("number_of_pins={}", cfg.number_of_pins),
+        format!("pins_output={}", join(&cfg.pins_output)),
+        format!("pins_output_backup={}", join(&cfg.pins_output_backup)),
+        format!("pins_input={}", join(&cfg.pins_input)),
+        format!("day_night_pin={}", cfg.day_night_pin),
+        format!("master_pin={}", cfg.master_pin.clone().unwrap_or_default()),
+        format!("most_restrictive_aspect={}", cfg.most_restrictive_aspect),
+    ];
+    for main in SIGNAL_ASPECTS {
+        if let Some(pattern) = cfg.signals.get(main) {
+            lines.push(format!("signals.{}={}", signal_name(main), join(pattern)));
+        }
+    }
+    for (name, flashing) in cfg.flashing.iter() {
+        lines.push(format!(
+            "flashing.{}={};{};{}",
+            name,
+            join(&flashing.pins),
+            flashing.frequency,
+            flashing.duty_cycle
+        ));
+    }
+    canonical_indicator(&mut lines, "zs3", &cfg.zs3);
+    canonical_indicator(&mut lines, "zs3v", &cfg.zs3v);
+    canonical_indicator(&mut lines, "zs2", &cfg.zs2);
+    canonical_indicator(&mut lines, "zs2v", &cfg.zs2v);
+    lines.push(format!("dark_switching={}", cfg.dark_switching));
+    lines.push(format!(
+        "primary_retest_interval={}",
+        cfg.primary_retest_interval
+            .map(|interval| interval.to_string())
+            .unwrap_or_default()
+    ));
+    let reactions: Vec<String> = cfg
+        .unexpected_lamp_reactions
+        .iter()
+        .map(|reaction| format!("{:?}", reaction))
+        .collect();
+    lines.push(format!("unexpected_lamp_reactions={}", join(&reactions)));
+    lines.push(format!("lamp_status_bytes={}", cfg.lamp_status_bytes));
+    for (name, substitutes) in cfg.degraded_aspects.iter() {
+        lines.push(format!("degraded_aspects.{}={}", name, join(substitutes)));
+    }
+    lines.push(format!("transition_mode={:?}", cfg.transition_mode));
+    lines.push(format!(
+        "transition_dwell_time={}",
+        cfg.transition_dwell_time
+    ));
+    for transition in cfg.transitions.iter() {
+        let intermediate: Vec<String> = transition
+            .intermediate
+            .iter()
+            .map(|pattern| join(pattern))
+            .collect();
+        lines.push(format!(
+            "transitions.{}->{}={};{};{}",
+            transition.from,
+            transition.to,
+            transition
+                .mode
+                .map(|mode| format!("{:?}", mode))
+                .unwrap_or_default(),
+            intermediate.join("/"),
+            transition.dwell_time
+        ));
+    }
+    lines.join("\n")
+}
+
+// MD5 over the canonical config and the SCI name of the OC, reported to the interlocking in the
+// version response, so the interlocking can refuse unapproved configs
+pub fn config_checksum(cfg: &PinConfig, sci_name: &str) -> md5::Digest {
+    md5::compute(format!("{}\nsci_name={}", canonical_config(cfg), sci_name))
+}
+
+// parses and validates the given config file, all problems are returned at once
+pub fn read_config(config_file_path: &str) -> Result<PinConfig, ConfigError> {
+    if !Path::new(config_file_path).exists() {
+        return Err(ConfigError::NotFound(config_file_path.to_string()));
+    }
+    let invalid = |problems| ConfigError::Invalid(config_file_path.to_string(), problems);
+    let cfg = PinConfig::from_config_file(config_file_path).map_err(|e| {
+        invalid(vec![ConfigProblem {
+            line: None,
+            message: format!("COULD NOT PARSE CONFIG FILE: {}", e),
+        }])
+    })?;
+    let text = fs::read_to_string(config_file_path).unwrap_or_default();
+    let problems = validate_config(&cfg, &text);
+    if problems.is_empty() {
+        Ok(cfg)
+    } else {
+        Err(invalid(problems))
+    }
+}
+
+// path of the config file given at config_arg_pos, None if the default pins are requested
+pub fn config_path(config_arg_pos: usize) -> Option<String> {
     let args: Vec<String> = env::args().collect();
-    println!("ARGS {:?}", { args.clone() });
     let mut config_file_path = "./config/pin_config.toml";
-    let mut cfg = PinConfig::default();
     if args.len() > config_arg_pos {
         config_file_path = &args[config_arg_pos];
     } else {
@@ -63,20 +1007,121 @@ pub fn get_config(config_arg_pos: usize) -> PinConfig {
             config_file_path
         )
     }
-    println!("CONFIG FILE {:?}", { config_file_path });
-    let config_path = Path::new(config_file_path);
-    if !config_path.exists() {
-        println!("NO CONFIG FILE FOUND, CONTINUE USING DEFAULT VALUES")
-    } else {
-        cfg = PinConfig::from_config_file(config_file_path).unwrap();
-        println!("CONFIG FILE:{:?}", cfg.clone());
-        if cfg.pins_output.len() != cfg.number_of_pins
-            || cfg.pins_input.len() != cfg.number_of_pins
-            || cfg.pins_output_backup.len() != cfg.number_of_pins
-        {
-            eprintln!("Error: NUMBER OF PINS DOES NOT MATCH, PLEASE CHECK THE CONFIG FILE!");
-            std::process::exit(1);
+    if config_file_path == DEFAULT_CONFIG_FLAG {
+        return None;
+    }
+    Some(config_file_path.to_string())
+}
+
+// the default pins are only used if requested with DEFAULT_CONFIG_FLAG, a missing or invalid
+// config file is an error the caller has to handle
+pub fn get_config(config_arg_pos: usize) -> Result<PinConfig, ConfigError> {
+    let args: Vec<String> = env::args().collect();
+    println!("ARGS {:?}", { args.clone() });
+    let config_file_path = match config_path(config_arg_pos) {
+        Some(config_file_path) => config_file_path,
+        None => {
+            println!("USING THE DEFAULT PIN CONFIG AS REQUESTED");
+            return Ok(PinConfig::default());
         }
+    };
+    println!("CONFIG FILE {:?}", { &config_file_path });
+    let cfg = read_config(&config_file_path)?;
+    println!("CONFIG FILE:{:?}", cfg.clone());
+    Ok(cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_ignores_the_hardware_selection() {
+        let cfg = PinConfig::default();
+        let mut simulated = cfg.clone();
+        simulated.backend = IOBackendType::Simulated;
+        simulated.simulator.control_socket = Some("127.0.0.1:7070".to_string());
+        assert_eq!(config_checksum(&cfg, "S"), config_checksum(&simulated, "S"));
+    }
+
+    #[test]
+    fn checksum_covers_patterns_and_sci_name() {
+        let cfg = PinConfig::default();
+        let mut changed = cfg.clone();
+        changed.signals.insert(SCILSMain::Ks2, vec![0, 0, 0, 1]);
+        assert_ne!(config_checksum(&cfg, "S"), config_checksum(&changed, "S"));
+        assert_ne!(config_checksum(&cfg, "S"), config_checksum(&cfg, "T"));
+    }
+
+    #[test]
+    fn flashing_lamps_need_an_on_phase_the_check_can_see() {
+        let mut cfg = PinConfig::default();
+        cfg.signals.insert(SCILSMain::Ks1Flashing, vec![0, 1, 0, 0]);
+        let mut problems = |frequency: f64, duty_cycle: f64| {
+            cfg.flashing.insert(
+                "Ks1Flashing".to_string(),
+                FlashingConfig {
+                    pins: vec![0, 1, 0, 0],
+                    frequency,
+                    duty_cycle,
+                },
+            );
+            validate_config(&cfg, "")
+                .iter()
+                .filter(|problem| problem.message.contains("FLASHING SIGNAL"))
+                .count()
+        };
+        assert_eq!(problems(1.0, 0.5), 0);
+        assert_eq!(problems(1.0, 0.0), 1);
+        assert_eq!(problems(10.0, 0.5), 1);
+    }
+
+    #[test]
+    fn pins_are_only_used_once() {
+        let pins = |names: [&str; 4]| names.iter().map(|pin| pin.to_string()).collect();
+        let mut cfg = PinConfig {
+            pins_output_backup: pins(["O_11", "O_12", "O_13", "O_14"]),
+            pins_input: pins(["I_1", "I_2", "I_3", "I_4"]),
+            ..Default::default()
+        };
+        assert!(validate_config(&cfg, "").is_empty());
+        cfg.master_pin = Some("O_7".to_string());
+        cfg.zs3 = Some(IndicatorConfig {
+            pins: vec!["O_12".to_string()],
+            signals: BTreeMap::new(),
+        });
+        let problems: Vec<String> = validate_config(&cfg, "")
+            .into_iter()
+            .map(|problem| problem.message)
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                "PIN O_12 IS USED MORE THAN ONCE: pins_output_backup, zs3",
+                "PIN O_7 IS USED MORE THAN ONCE: day_night_pin, master_pin",
+            ]
+        );
+    }
+
+    #[test]
+    fn problems_point_into_their_table() {
+        let text = "signals={\"Off\"=[0,0]}\n\n[zs3]\npins=[\"O_9\"]\nsignals={\"Off\"=[0,0]}\n\n\
+            [[transitions]]\nintermediate=[[1,0]]\n\n[[transitions]]\nfrom=\"Ks2\"\nintermediate=[[0,1]]\n";
+        assert_eq!(find_line(text, &Section::Top, "signals"), Some(1));
+        assert_eq!(find_line(text, &table("zs3"), "Off"), Some(5));
+        assert_eq!(find_line(text, &table("zs3"), "Index4"), Some(3));
+        let second_transition = Section::ArrayTable("transitions".to_string(), 1);
+        assert_eq!(
+            find_line(text, &second_transition, "intermediate"),
+            Some(12)
+        );
+        assert_eq!(find_line(text, &table("zs2"), "Off"), None);
+    }
+
+    #[test]
+    fn canonical_config_starts_with_its_version() {
+        let canonical = canonical_config(&PinConfig::default());
+        assert!(canonical.starts_with("version=1\n"));
+        assert!(canonical.contains("signals.Ks1=1,1,1,1"));
     }
-    cfg
 }
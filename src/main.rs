@@ -1,26 +1,42 @@
+mod blink;
+mod io_backend;
 mod io_config;
 mod oc_interface;
+mod sci_config;
+mod simulator;
+mod transport;
 
 pub mod rasta_grpc {
     tonic::include_proto!("sci");
 }
 
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fs, io, thread};
 
 use clokwerk::{Scheduler, TimeUnits};
-use io_config::PinConfig;
-use md5;
-use rasta_grpc::rasta_client::RastaClient;
-use rasta_grpc::SciPacket;
-use sci_rs::scils::{SCILSBrightness, SCILSMain, SCILSSignalAspect};
-use sci_rs::{ProtocolType, SCIMessageType, SCITelegram, SCIVersionCheckResult};
+use io_config::{canonical_config, config_checksum, signal_name, PinConfig};
+use sci_config::SciConfig;
+use sci_rs::scils::{SCILSBrightness, SCILSSignalAspect};
+use sci_rs::{ProtocolType, SCICloseReason, SCIMessageType, SCITelegram, SCIVersionCheckResult};
+use tokio::sync::{mpsc, watch};
 use tokio::time;
-use tonic::Request;
+use transport::{SciLink, SciTransport, TransportError};
 
-const SEND_INTERVAL_MS: u64 = 500;
 const SCI_LS_VERSION: u8 = 0x03;
+// delay before reconnecting to the interlocking, doubled after every attempt without a handshake
+const RECONNECT_MIN_DELAY_MS: u64 = 1000;
+const RECONNECT_MAX_DELAY_MS: u64 = 30000;
+// time the outbound stream gets to send the telegrams left when a connection ends
+const SEND_FLUSH_TIMEOUT_MS: u64 = 1000;
+// main, additional, zs3, zs3v, zs2, zs2v, deprecation, upstream and downstream driveway,
+// dark switching and 9 bytes of nationally specified information
+const SIGNAL_ASPECT_PAYLOAD_LEN: usize = 19;
+
+// sends telegrams to the interlocking over the current connection, None while disconnected
+type SendChannel = RwLock<Option<mpsc::UnboundedSender<SCITelegram>>>;
 
 #[derive(PartialEq, Clone, Debug)]
 enum InterlockingConnectionState {
@@ -38,9 +54,206 @@ fn check_version(sender_version: u8) -> SCIVersionCheckResult {
     }
 }
 
-// MD5 (16 bytes)
-fn compute_checksum(pseudo_telegram: SCITelegram) -> Vec<u8> {
-    md5::compute::<Vec<u8>>(pseudo_telegram.into()).to_vec()
+// a received telegram that is not executed, the connection is closed with close_reason()
+#[derive(Debug)]
+enum TelegramError {
+    Undecodable(String),
+    PayloadTooShort {
+        message_type: &'static str,
+        used: usize,
+        required: usize,
+    },
+    InvalidPayload {
+        message_type: &'static str,
+        error: String,
+    },
+    UnexpectedTelegram {
+        message_type: &'static str,
+        state: InterlockingConnectionState,
+    },
+}
+
+impl TelegramError {
+    fn close_reason(&self) -> SCICloseReason {
+        match self {
+            TelegramError::Undecodable(_) | TelegramError::PayloadTooShort { .. } => {
+                SCICloseReason::FormalTelegramError
+            }
+            TelegramError::InvalidPayload { .. } => SCICloseReason::ContentTelegramError,
+            TelegramError::UnexpectedTelegram { .. } => SCICloseReason::ProtocolError,
+        }
+    }
+}
+
+impl fmt::Display for TelegramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TelegramError::Undecodable(error) => {
+                write!(f, "packet is no SCI telegram ({})", error)
+            }
+            TelegramError::PayloadTooShort {
+                message_type,
+                used,
+                required,
+            } => write!(
+                f,
+                "payload of {} telegram has {} bytes, {} required",
+                message_type, used, required
+            ),
+            TelegramError::InvalidPayload {
+                message_type,
+                error,
+            } => write!(
+                f,
+                "invalid payload of {} telegram ({})",
+                message_type, error
+            ),
+            TelegramError::UnexpectedTelegram {
+                message_type,
+                state,
+            } => write!(
+                f,
+                "{} telegram not allowed in state {:?}",
+                message_type, state
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TelegramError {}
+
+// the used part of the payload, which has to hold at least the required bytes
+fn payload(sci_telegram: &SCITelegram, required: usize) -> Result<&[u8], TelegramError> {
+    let data = &sci_telegram.payload.data;
+    let used = sci_telegram.payload.used.min(data.len());
+    if used < required {
+        return Err(TelegramError::PayloadTooShort {
+            message_type: message_type_name(&sci_telegram.message_type),
+            used,
+            required,
+        });
+    }
+    Ok(&data[..used])
+}
+
+fn invalid_payload(sci_telegram: &SCITelegram, error: impl fmt::Debug) -> TelegramError {
+    TelegramError::InvalidPayload {
+        message_type: message_type_name(&sci_telegram.message_type),
+        error: format!("{:?}", error),
+    }
+}
+
+fn message_type_name(message_type: &SCIMessageType) -> &'static str {
+    message_type
+        .try_as_sci_message_type()
+        .or_else(|_| message_type.try_as_scils_message_type())
+        .unwrap_or("UNKNOWN")
+}
+
+// Unconnected -> VersionResponseSent -> Connected, signal commands are only accepted while
+// connected, release for maintenance and close are accepted in every state
+fn telegram_allowed(message_type: &SCIMessageType, state: &InterlockingConnectionState) -> bool {
+    if *message_type == SCIMessageType::sci_release_for_maintenance()
+        || *message_type == SCIMessageType::sci_close()
+    {
+        return true;
+    }
+    match state {
+        InterlockingConnectionState::Unconnected => {
+            *message_type == SCIMessageType::sci_version_request()
+        }
+        InterlockingConnectionState::VersionResponseSent => {
+            *message_type == SCIMessageType::sci_status_request()
+        }
+        InterlockingConnectionState::Connected => {
+            *message_type == SCIMessageType::scils_show_signal_aspect()
+                || *message_type == SCIMessageType::scils_change_brightness()
+        }
+        InterlockingConnectionState::Terminated => false,
+    }
+}
+
+// watches the pin config file, a changed and valid config waits until the signal shows the most
+// restrictive aspect and then replaces the active one
+struct ConfigReload {
+    path: Option<String>,
+    modified: Option<SystemTime>,
+    pending: Option<PinConfig>,
+    // counts the replaced configs, the connection ends on a change so the interlocking starts a
+    // new version handshake and receives the new checksum
+    replaced: watch::Sender<usize>,
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl ConfigReload {
+    fn new(path: Option<String>) -> Self {
+        let modified = path.as_deref().and_then(modified_time);
+        Self {
+            path,
+            modified,
+            pending: None,
+            replaced: watch::channel(0).0,
+        }
+    }
+
+    // notified when a config replaces the active one after the call
+    fn subscribe(&self) -> watch::Receiver<usize> {
+        self.replaced.subscribe()
+    }
+
+    // reads the config file again if it changed since the last read or if forced
+    fn check_file(&mut self, io_cfg: &PinConfig, force: bool) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => {
+                if force {
+                    println!("Running on the default pin config, there is no file to reload");
+                }
+                return;
+            }
+        };
+        let modified = modified_time(path);
+        if !force && modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match io_config::read_config(path) {
+            Ok(new_cfg) if canonical_config(&new_cfg) == canonical_config(io_cfg) => {
+                println!("Pin config {} is unchanged", path);
+                self.pending = None;
+            }
+            Ok(new_cfg) => {
+                println!(
+                    "New pin config {} is applied once the signal shows {}",
+                    path, io_cfg.most_restrictive_aspect
+                );
+                self.pending = Some(new_cfg);
+            }
+            Err(e) => eprintln!("Error: PIN CONFIG NOT RELOADED: {}", e),
+        }
+    }
+
+    // swaps the config while the most restrictive aspect is shown, returns true if it was swapped
+    fn apply(&mut self, oc: &mut oc_interface::OC, io_cfg: &RwLock<PinConfig>) -> bool {
+        if self.pending.is_none() {
+            return false;
+        }
+        let mut locked_io_cfg = io_cfg.write().unwrap();
+        if signal_name(oc.main_aspect) != locked_io_cfg.most_restrictive_aspect {
+            return false;
+        }
+        let new_cfg = self.pending.take().unwrap();
+        oc.apply_config(&locked_io_cfg, &new_cfg);
+        println!("Pin config reloaded, its checksum is reported at the next version handshake");
+        *locked_io_cfg = new_cfg;
+        self.replaced.send_modify(|count| *count += 1);
+        true
+    }
 }
 
 fn handle_incoming_telegram(
@@ -48,59 +261,72 @@ fn handle_incoming_telegram(
     sci_telegram: SCITelegram,
     state: &mut InterlockingConnectionState,
     io_cfg: PinConfig,
-) -> Vec<SCITelegram> {
-    if sci_telegram.message_type == SCIMessageType::scils_show_signal_aspect() {
+    sci_cfg: &SciConfig,
+) -> Result<Vec<SCITelegram>, TelegramError> {
+    let oc_name = &*sci_cfg.oc.sci_name;
+    let interlocking_name = &*sci_cfg.interlocking.sci_name;
+    if sci_telegram.receiver != oc_name {
+        println!(
+            "Rejected telegram addressed to {} from {}, this OC is {}",
+            sci_telegram.receiver, sci_telegram.sender, oc_name
+        );
+        return Ok(vec![]);
+    }
+    if !telegram_allowed(&sci_telegram.message_type, state) {
+        return Err(TelegramError::UnexpectedTelegram {
+            message_type: message_type_name(&sci_telegram.message_type),
+            state: state.clone(),
+        });
+    }
+    let responses = if sci_telegram.message_type == SCIMessageType::scils_show_signal_aspect() {
         let status_change =
-            SCILSSignalAspect::try_from(sci_telegram.payload.data.as_slice()).unwrap();
+            SCILSSignalAspect::try_from(payload(&sci_telegram, SIGNAL_ASPECT_PAYLOAD_LEN)?)
+                .map_err(|e| invalid_payload(&sci_telegram, e))?;
         println!(
             "Received show signal aspect telegram: changing main to {:?}",
             status_change.main()
         );
         oc.show_signal_aspect(status_change, io_cfg.clone());
         vec![SCITelegram::scils_signal_aspect_status(
-            &*sci_telegram.receiver,
-            &*sci_telegram.sender,
-            oc.signal_aspect_status(),
+            oc_name,
+            interlocking_name,
+            oc.signal_aspect_status(&io_cfg),
         )]
     } else if sci_telegram.message_type == SCIMessageType::scils_change_brightness() {
-        let brightness_change = SCILSBrightness::try_from(sci_telegram.payload.data[0]).unwrap();
+        let brightness_change = SCILSBrightness::try_from(payload(&sci_telegram, 1)?[0])
+            .map_err(|e| invalid_payload(&sci_telegram, e))?;
         println!(
             "Received change brightness telegram: changing brightness to {:?}",
             brightness_change
         );
         oc.change_brightness(brightness_change, io_cfg.clone());
         vec![SCITelegram::scils_brightness_status(
-            &*sci_telegram.receiver,
-            &*sci_telegram.sender,
+            oc_name,
+            interlocking_name,
             oc.brightness_status(),
         )]
     } else if sci_telegram.message_type == SCIMessageType::sci_version_request() {
-        let check_result = check_version(sci_telegram.payload.data[0]);
-        *state = InterlockingConnectionState::VersionResponseSent;
+        let check_result = check_version(payload(&sci_telegram, 1)?[0]);
         if check_result == SCIVersionCheckResult::VersionsAreEqual {
+            *state = InterlockingConnectionState::VersionResponseSent;
             println!("Received version request - sending version response telegram -> version check successful");
-            let checksum = compute_checksum(SCITelegram::version_response(
-                ProtocolType::SCIProtocolLS,
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
-                SCI_LS_VERSION,
-                check_result,
-                &[0],
-            ));
-            return vec![SCITelegram::version_response(
+            // MD5 (16 bytes) over the active pin config and the SCI name of the OC
+            let checksum = config_checksum(&io_cfg, oc_name);
+            println!("Reporting pin config checksum {:x}", checksum);
+            vec![SCITelegram::version_response(
                 ProtocolType::SCIProtocolLS,
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
+                oc_name,
+                interlocking_name,
                 SCI_LS_VERSION,
                 check_result,
-                checksum.as_slice(),
-            )];
+                &checksum[..],
+            )]
         } else {
             println!("Received version request - sending version response telegram -> version check failed");
             vec![SCITelegram::version_response(
                 ProtocolType::SCIProtocolLS,
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
+                oc_name,
+                interlocking_name,
                 SCI_LS_VERSION,
                 check_result,
                 &[0],
@@ -110,148 +336,675 @@ fn handle_incoming_telegram(
         println!("Received status request - sending status telegrams");
         *state = InterlockingConnectionState::Connected;
         vec![
-            SCITelegram::status_begin(
-                ProtocolType::SCIProtocolLS,
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
-            ),
+            SCITelegram::status_begin(ProtocolType::SCIProtocolLS, oc_name, interlocking_name),
             SCITelegram::scils_signal_aspect_status(
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
-                oc.signal_aspect_status(),
+                oc_name,
+                interlocking_name,
+                oc.signal_aspect_status(&io_cfg),
             ),
             SCITelegram::scils_brightness_status(
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
+                oc_name,
+                interlocking_name,
                 oc.brightness_status(),
             ),
-            SCITelegram::status_finish(
-                ProtocolType::SCIProtocolLS,
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
-            ),
+            SCITelegram::status_finish(ProtocolType::SCIProtocolLS, oc_name, interlocking_name),
         ]
     } else if sci_telegram.message_type == SCIMessageType::sci_release_for_maintenance()
         || sci_telegram.message_type == SCIMessageType::sci_close()
     {
-        println!("Received release for maintenance or close - shutting down now!");
+        println!("Received release for maintenance or close - closing the connection");
         *state = InterlockingConnectionState::Terminated;
         vec![]
     } else {
         println!(
             "Cannot handle received telegram of type {}!",
-            sci_telegram
-                .message_type
-                .try_as_sci_message_type()
-                .unwrap_or("UNKNOWN")
+            message_type_name(&sci_telegram.message_type)
         );
         vec![]
+    };
+    Ok(responses)
+}
+
+// state shared by the connection to the interlocking, the signal checks and the maintenance
+// commands, the OC is always locked before the pin config
+struct SharedState {
+    oc: RwLock<oc_interface::OC>,
+    // the pin config can be replaced at runtime, see ConfigReload
+    io_cfg: RwLock<PinConfig>,
+    send_channel: SendChannel,
+    // SCI names of the OC and the interlocking, set while the connection is established
+    sci_names: RwLock<Option<(String, String)>>,
+    // received telegrams that were malformed or not allowed, over all connections
+    telegram_errors: AtomicUsize,
+    config_reload: Mutex<ConfigReload>,
+}
+
+impl SharedState {
+    fn new(oc: oc_interface::OC, io_cfg: PinConfig, config_reload: ConfigReload) -> Self {
+        Self {
+            oc: RwLock::new(oc),
+            io_cfg: RwLock::new(io_cfg),
+            send_channel: RwLock::new(None),
+            sci_names: RwLock::new(None),
+            telegram_errors: AtomicUsize::new(0),
+            config_reload: Mutex::new(config_reload),
+        }
+    }
+}
+
+// informs the interlocking about lamp failures without waiting for its next command
+fn send_lamp_status(oc: &oc_interface::OC, io_cfg: &PinConfig, shared: &SharedState) {
+    if let (Some((oc_name, interlocking_name)), Some(sender)) = (
+        &*shared.sci_names.read().unwrap(),
+        &*shared.send_channel.read().unwrap(),
+    ) {
+        println!("Lamp status changed - sending signal aspect status telegram");
+        let _ = sender.send(SCITelegram::scils_signal_aspect_status(
+            oc_name,
+            interlocking_name,
+            oc.signal_aspect_status(io_cfg),
+        ));
+    }
+}
+
+fn show_most_restrictive_aspect(shared: &SharedState) {
+    let mut locked_oc = shared.oc.write().unwrap();
+    let io_cfg = shared.io_cfg.read().unwrap().clone();
+    locked_oc.show_signal_aspect(
+        oc_interface::most_restrictive_aspect(&io_cfg),
+        io_cfg.clone(),
+    );
+}
+
+// maintenance commands:
+// - reload: read the pin config file again, it is applied while the most restrictive aspect is shown
+// - recover <input pin>: switch the lamp back from its backup line to the primary line
+// - recover all: the same for all lamps on their backup line
+// - failovers: print the number of switches to the backup line per input pin
+// - power on: switch the lamp power on again after it was cut through the master pin
+// - telegram errors: print the number of received telegrams that closed the connection
+fn handle_maintenance_command(
+    oc: &mut oc_interface::OC,
+    command: &str,
+    shared: &SharedState,
+) -> bool {
+    let io_cfg = &shared.io_cfg.read().unwrap().clone();
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        ["reload"] => {
+            let mut locked_config_reload = shared.config_reload.lock().unwrap();
+            locked_config_reload.check_file(io_cfg, true);
+            locked_config_reload.apply(oc, &shared.io_cfg)
+        }
+        ["recover", "all"] => {
+            let input_pins: Vec<String> = oc.backup_map.keys().cloned().collect();
+            let mut recovered = false;
+            for input_pin in input_pins {
+                recovered |= oc.retest_primary_line(&input_pin, io_cfg);
+            }
+            recovered
+        }
+        ["recover", input_pin] => oc.retest_primary_line(input_pin, io_cfg),
+        ["power", "on"] => {
+            oc.set_power(true, io_cfg);
+            oc.unexpected_lamps.clear();
+            true
+        }
+        ["telegram", "errors"] => {
+            println!(
+                "Telegram errors: {}",
+                shared.telegram_errors.load(Ordering::Relaxed)
+            );
+            false
+        }
+        ["failovers"] => {
+            println!("Backup lines in use: {:?}", oc.backup_map);
+            println!("Failovers per pin: {:?}", oc.failover_counts);
+            false
+        }
+        [] => false,
+        _ => {
+            println!("Unknown maintenance command {}", command.trim());
+            false
+        }
+    }
+}
+
+// decodes a received packet and handles the telegram, packets that are no SCI telegram are errors
+fn handle_packet(
+    oc: &mut oc_interface::OC,
+    message: &[u8],
+    state: &mut InterlockingConnectionState,
+    io_cfg: PinConfig,
+    sci_cfg: &SciConfig,
+) -> Result<Vec<SCITelegram>, TelegramError> {
+    let sci_telegram: SCITelegram = message
+        .try_into()
+        .map_err(|e| TelegramError::Undecodable(format!("{:?}", e)))?;
+    handle_incoming_telegram(oc, sci_telegram, state, io_cfg, sci_cfg)
+}
+
+// the next received packet, Err once supervision_timeout passes without one
+async fn receive(
+    incoming: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+    supervision_timeout: Option<Duration>,
+) -> Result<Option<Vec<u8>>, time::error::Elapsed> {
+    match supervision_timeout {
+        Some(supervision_timeout) => time::timeout(supervision_timeout, incoming.recv()).await,
+        None => Ok(incoming.recv().await),
     }
 }
 
+// one connection to the interlocking, starting with a fresh handshake in Unconnected, returns when
+// the connection ends or is terminated
+async fn run_connection(
+    transport: &mut dyn SciTransport,
+    shared: &SharedState,
+    sci_cfg: &SciConfig,
+    handshake_completed: &mut bool,
+) -> Result<(), TransportError> {
+    let SciLink {
+        outgoing: sender,
+        mut incoming,
+        flushed,
+    } = transport.connect().await?;
+    println!("Connected to {}", transport.name());
+
+    let mut conn_state = InterlockingConnectionState::Unconnected;
+
+    // telegrams are sent as soon as they are produced
+    *shared.send_channel.write().unwrap() = Some(sender.clone());
+
+    let mut config_replaced = shared.config_reload.lock().unwrap().subscribe();
+
+    let supervision_timeout = sci_cfg.supervision_timeout.map(Duration::from_millis);
+    loop {
+        let received = tokio::select! {
+            received = receive(&mut incoming, supervision_timeout) => received,
+            _ = config_replaced.changed() => {
+                println!("Pin config replaced - closing the connection for a new version handshake");
+                let _ = sender.send(SCITelegram::close(
+                    ProtocolType::SCIProtocolLS,
+                    &sci_cfg.oc.sci_name,
+                    &sci_cfg.interlocking.sci_name,
+                    SCICloseReason::NormalClose,
+                ));
+                *shared.sci_names.write().unwrap() = None;
+                break;
+            }
+        };
+        let message = match received {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(_) => {
+                if conn_state == InterlockingConnectionState::Unconnected {
+                    continue;
+                }
+                // the interlocking learns about the timeout, the next connection needs a new handshake
+                println!(
+                    "No telegram for {} ms - closing the connection with TimeoutError",
+                    sci_cfg.supervision_timeout.unwrap_or_default()
+                );
+                *shared.sci_names.write().unwrap() = None;
+                show_most_restrictive_aspect(shared);
+                let _ = sender.send(SCITelegram::close(
+                    ProtocolType::SCIProtocolLS,
+                    &sci_cfg.oc.sci_name,
+                    &sci_cfg.interlocking.sci_name,
+                    SCICloseReason::TimeoutError,
+                ));
+                break;
+            }
+        };
+        let received = Instant::now();
+        let names = (
+            sci_cfg.oc.sci_name.clone(),
+            sci_cfg.interlocking.sci_name.clone(),
+        );
+        let mut locked_oc = shared.oc.write().unwrap();
+        let io_cfg = shared.io_cfg.read().unwrap().clone();
+        let previous_state = conn_state.clone();
+        match handle_packet(&mut locked_oc, &message, &mut conn_state, io_cfg, sci_cfg) {
+            Ok(sci_responses) => {
+                for sci_response in sci_responses {
+                    let _ = sender.send(sci_response);
+                }
+                if previous_state == InterlockingConnectionState::VersionResponseSent
+                    && conn_state == InterlockingConnectionState::Connected
+                {
+                    println!("Status request answered in {:?}", received.elapsed());
+                }
+            }
+            Err(e) => {
+                let count = shared.telegram_errors.fetch_add(1, Ordering::Relaxed) + 1;
+                println!(
+                    "Telegram error #{}: {} - closing the connection with {:?}",
+                    count,
+                    e,
+                    e.close_reason()
+                );
+                conn_state = InterlockingConnectionState::Terminated;
+                let _ = sender.send(SCITelegram::close(
+                    ProtocolType::SCIProtocolLS,
+                    &sci_cfg.oc.sci_name,
+                    &sci_cfg.interlocking.sci_name,
+                    e.close_reason(),
+                ));
+            }
+        }
+        if conn_state == InterlockingConnectionState::Connected {
+            *handshake_completed = true;
+            *shared.sci_names.write().unwrap() = Some(names);
+        }
+        if conn_state == InterlockingConnectionState::Terminated {
+            *shared.sci_names.write().unwrap() = None;
+            break;
+        }
+    }
+
+    // the outbound stream still has to send a close telegram before the connection is dropped,
+    // telegrams that cannot be sent anymore are discarded
+    *shared.send_channel.write().unwrap() = None;
+    drop(sender);
+    let _ = time::timeout(Duration::from_millis(SEND_FLUSH_TIMEOUT_MS), flushed).await;
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let io_cfg = io_config::get_config(3);
-
-    let most_restrictive_aspect = SCILSSignalAspect::new(
-        SCILSMain::Ks2,
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        [0u8; 9],
-    );
+    let sci_cfg = sci_config::get_sci_config();
+    // the pin config and backend follow the bridge address, which only the grpc transport takes
+    let config_arg_pos = 1 + transport::transport_arg_count(&sci_cfg);
+    let io_cfg = match io_config::get_config(config_arg_pos) {
+        Ok(io_cfg) => io_cfg,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let io = io_backend::get_backend(&io_cfg, config_arg_pos + 1);
+    io_backend::warn_split_aspect_writes(&mut *io.lock().unwrap(), &io_cfg);
 
-    let bridge_ip_addr = std::env::args().nth(1).unwrap();
-    let bridge_port = std::env::args().nth(2).unwrap();
+    let most_restrictive_aspect = oc_interface::most_restrictive_aspect(&io_cfg);
 
-    let mut client =
-        RastaClient::connect(format!("http://{}:{}", bridge_ip_addr, bridge_port)).await?;
+    let mut transport = transport::get_transport(&sci_cfg, 1, 2);
     println!("OC software started!");
 
-    let oc = oc_interface::OC {
-        main_aspect: Default::default(),
-        main_aspect_string: "Off".to_string(),
-        brightness: SCILSBrightness::Day,
-        backup_map: HashMap::new(),
-    };
-    let lock_oc = RwLock::new(oc);
-    let main_lock_oc = Arc::new(lock_oc);
-    let check_lock_oc = main_lock_oc.clone();
+    let shared = Arc::new(SharedState::new(
+        oc_interface::OC::new(io),
+        io_cfg.clone(),
+        ConfigReload::new(io_config::config_path(config_arg_pos)),
+    ));
 
-    let check_io_cfg = io_cfg.clone();
+    let check_shared = shared.clone();
     let mut scheduler = Scheduler::new();
-    scheduler
-        .every(5.seconds())
-        .run(move || check_lock_oc.write().unwrap().check_signal(&check_io_cfg));
+    scheduler.every(5.seconds()).run(move || {
+        let mut locked_oc = check_shared.oc.write().unwrap();
+        let check_io_cfg = check_shared.io_cfg.read().unwrap().clone();
+        let mut lamp_status_changed = locked_oc.check_signal(&check_io_cfg);
+        lamp_status_changed |= locked_oc.retest_primary_lines(&check_io_cfg);
+        {
+            let mut locked_config_reload = check_shared.config_reload.lock().unwrap();
+            locked_config_reload.check_file(&check_io_cfg, false);
+            lamp_status_changed |= locked_config_reload.apply(&mut locked_oc, &check_shared.io_cfg);
+        }
+        if lamp_status_changed {
+            // the config may have been replaced by the reload
+            let check_io_cfg = check_shared.io_cfg.read().unwrap().clone();
+            send_lamp_status(&locked_oc, &check_io_cfg, &check_shared);
+        }
+    });
+
+    let maintenance_shared = shared.clone();
+    thread::spawn(move || {
+        let mut input_string = String::new();
+        while io::stdin().read_line(&mut input_string).unwrap_or(0) > 0 {
+            let mut locked_oc = maintenance_shared.oc.write().unwrap();
+            if handle_maintenance_command(&mut locked_oc, &input_string, &maintenance_shared) {
+                let io_cfg = maintenance_shared.io_cfg.read().unwrap().clone();
+                send_lamp_status(&locked_oc, &io_cfg, &maintenance_shared);
+            }
+            input_string.clear();
+        }
+    });
 
     {
         // establish initial state of outputs
-        let mut locked_oc = main_lock_oc.write().unwrap();
-        locked_oc.show_signal_aspect(most_restrictive_aspect.clone(), io_cfg.clone());
+        let mut locked_oc = shared.oc.write().unwrap();
+        locked_oc.set_power(true, &io_cfg);
+        locked_oc.show_signal_aspect(most_restrictive_aspect, io_cfg.clone());
         locked_oc.change_brightness(SCILSBrightness::Day, io_cfg.clone());
     }
 
-    let check_thread = scheduler.watch_thread(Duration::from_millis(5000));
-    let send_queue: VecDeque<SCITelegram> = VecDeque::new();
-    let lock_queue = RwLock::new(send_queue);
-    let receive_lock_queue = Arc::new(lock_queue);
-    let send_lock_queue = receive_lock_queue.clone();
+    // the signal checks run as long as the handle exists
+    let _check_thread = scheduler.watch_thread(Duration::from_millis(5000));
 
-    let mut conn_state = InterlockingConnectionState::Unconnected;
+    // the OC keeps the most restrictive aspect and its lamp supervision while it reconnects
+    let mut reconnect_delay = RECONNECT_MIN_DELAY_MS;
+    loop {
+        let mut handshake_completed = false;
+        if let Err(e) = run_connection(
+            transport.as_mut(),
+            &shared,
+            &sci_cfg,
+            &mut handshake_completed,
+        )
+        .await
+        {
+            eprintln!("Error: CONNECTION TO THE INTERLOCKING FAILED: {}", e);
+        }
+        *shared.sci_names.write().unwrap() = None;
+        *shared.send_channel.write().unwrap() = None;
+
+        // fallback when connection is interrupted
+        show_most_restrictive_aspect(&shared);
 
-    let outbound = async_stream::stream! {
-        let mut interval = time::interval(Duration::from_millis(SEND_INTERVAL_MS));
-        while let time = interval.tick().await {
-            let mut message = Vec::new();
-            {
-                let mut locked_send_queue = send_lock_queue.write().unwrap();
-                if let Some(telegram) = locked_send_queue.pop_front() {
-                    message = telegram.into();
+        if handshake_completed {
+            reconnect_delay = RECONNECT_MIN_DELAY_MS;
+        }
+        println!("Reconnecting in {} ms", reconnect_delay);
+        time::sleep(Duration::from_millis(reconnect_delay)).await;
+        reconnect_delay = (reconnect_delay * 2).min(RECONNECT_MAX_DELAY_MS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io_backend::{SharedIOBackend, SimulatedBackend};
+    use sci_config::{SciEndpoint, TransportType};
+    use tokio::sync::oneshot;
+
+    fn test_sci_config() -> SciConfig {
+        SciConfig {
+            oc: SciEndpoint {
+                sci_name: "S".to_string(),
+                rasta_id: 1337,
+            },
+            interlocking: SciEndpoint {
+                sci_name: "C".to_string(),
+                rasta_id: 42,
+            },
+            transport: TransportType::Grpc,
+            rasta_address: "127.0.0.1:8888".parse().unwrap(),
+            supervision_timeout: None,
+            keep_alive_interval: 2000,
+            keep_alive_timeout: 8000,
+        }
+    }
+
+    // the simulated backend knows the pins of PinConfig::default() and injects no faults
+    fn test_oc(io_cfg: &PinConfig) -> oc_interface::OC {
+        let io: SharedIOBackend = Arc::new(Mutex::new(SimulatedBackend::new(io_cfg)));
+        oc_interface::OC::new(io)
+    }
+
+    fn states() -> [InterlockingConnectionState; 4] {
+        [
+            InterlockingConnectionState::Unconnected,
+            InterlockingConnectionState::VersionResponseSent,
+            InterlockingConnectionState::Connected,
+            InterlockingConnectionState::Terminated,
+        ]
+    }
+
+    // one valid telegram of every message type, sent by the interlocking to the OC
+    fn telegrams(io_cfg: &PinConfig) -> Vec<SCITelegram> {
+        let ls = ProtocolType::SCIProtocolLS;
+        vec![
+            SCITelegram::version_request(ls, "C", "S", SCI_LS_VERSION),
+            SCITelegram::version_response(
+                ls,
+                "C",
+                "S",
+                SCI_LS_VERSION,
+                SCIVersionCheckResult::VersionsAreEqual,
+                &[0; 16],
+            ),
+            SCITelegram::status_request(ls, "C", "S"),
+            SCITelegram::status_begin(ls, "C", "S"),
+            SCITelegram::status_finish(ls, "C", "S"),
+            SCITelegram::release_for_maintenance(ls, "C", "S"),
+            SCITelegram::close(ls, "C", "S", SCICloseReason::NormalClose),
+            SCITelegram::scils_show_signal_aspect(
+                "C",
+                "S",
+                oc_interface::most_restrictive_aspect(io_cfg),
+            ),
+            SCITelegram::scils_signal_aspect_status(
+                "C",
+                "S",
+                oc_interface::most_restrictive_aspect(io_cfg),
+            ),
+            SCITelegram::scils_change_brightness("C", "S", SCILSBrightness::Night),
+            SCITelegram::scils_brightness_status("C", "S", SCILSBrightness::Day),
+        ]
+    }
+
+    // the payload of these telegrams is read by the OC
+    fn needs_payload(sci_telegram: &SCITelegram) -> bool {
+        sci_telegram.message_type == SCIMessageType::sci_version_request()
+            || sci_telegram.message_type == SCIMessageType::scils_show_signal_aspect()
+            || sci_telegram.message_type == SCIMessageType::scils_change_brightness()
+    }
+
+    // xorshift, so every run feeds the same packets
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn bytes(&mut self, max_len: u64) -> Vec<u8> {
+            (0..self.next() % max_len)
+                .map(|_| self.next() as u8)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn arbitrary_packets_do_not_panic() {
+        let io_cfg = PinConfig::default();
+        let sci_cfg = test_sci_config();
+        let mut oc = test_oc(&io_cfg);
+        // headers of valid telegrams, followed by random payloads below
+        let headers: Vec<Vec<u8>> = telegrams(&io_cfg)
+            .into_iter()
+            .map(|mut sci_telegram| {
+                sci_telegram.payload.used = 0;
+                Vec::from(sci_telegram)
+            })
+            .collect();
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        for state in states() {
+            for _ in 0..2000 {
+                let noise = random.bytes(140);
+                let mut with_header = headers[random.next() as usize % headers.len()].clone();
+                with_header.extend(random.bytes(30));
+                for packet in [noise, with_header] {
+                    let mut packet_state = state.clone();
+                    let _ = handle_packet(
+                        &mut oc,
+                        &packet,
+                        &mut packet_state,
+                        io_cfg.clone(),
+                        &sci_cfg,
+                    );
                 }
             }
-            if message.len() > 0 {
-                yield SciPacket {message};
-            }
         }
-    };
+    }
 
-    let response = client.stream(Request::new(outbound)).await?;
-    let mut inbound = response.into_inner();
-
-    while let Some(sci_packet) = inbound.message().await? {
-        let sci_telegram = sci_packet
-            .message
-            .as_slice()
-            .try_into()
-            .unwrap_or_else(|e| panic!("Could not convert packet into SCITelegram: {:?}", e));
-        let mut locked_send_queue = receive_lock_queue.write().unwrap();
-        let mut locked_oc = main_lock_oc.write().unwrap();
-        for sci_response in handle_incoming_telegram(
-            &mut locked_oc,
-            sci_telegram,
-            &mut conn_state,
-            io_cfg.clone(),
-        ) {
-            locked_send_queue.push_back(sci_response);
+    #[test]
+    fn truncated_payloads_close_with_formal_error() {
+        let io_cfg = PinConfig::default();
+        let sci_cfg = test_sci_config();
+        let mut oc = test_oc(&io_cfg);
+        for sci_telegram in telegrams(&io_cfg) {
+            for state in states() {
+                if !telegram_allowed(&sci_telegram.message_type, &state) {
+                    continue;
+                }
+                for used in 0..sci_telegram.payload.used {
+                    let mut truncated = sci_telegram.clone();
+                    truncated.payload.used = used;
+                    let result = handle_incoming_telegram(
+                        &mut oc,
+                        truncated,
+                        &mut state.clone(),
+                        io_cfg.clone(),
+                        &sci_cfg,
+                    );
+                    if needs_payload(&sci_telegram) {
+                        assert!(matches!(result, Err(TelegramError::PayloadTooShort { .. })));
+                    }
+                }
+                // cut anywhere, including the header
+                let packet = Vec::from(sci_telegram.clone());
+                for len in 0..packet.len() {
+                    let result = handle_packet(
+                        &mut oc,
+                        &packet[..len],
+                        &mut state.clone(),
+                        io_cfg.clone(),
+                        &sci_cfg,
+                    );
+                    if needs_payload(&sci_telegram) {
+                        assert_eq!(
+                            result.map_err(|e| e.close_reason()).err(),
+                            Some(SCICloseReason::FormalTelegramError)
+                        );
+                    }
+                }
+            }
         }
-        if conn_state == InterlockingConnectionState::Terminated {
-            break;
+    }
+
+    #[test]
+    fn telegrams_outside_their_state_close_with_protocol_error() {
+        let io_cfg = PinConfig::default();
+        let sci_cfg = test_sci_config();
+        let mut oc = test_oc(&io_cfg);
+        for sci_telegram in telegrams(&io_cfg) {
+            for state in states() {
+                let mut telegram_state = state.clone();
+                let result = handle_incoming_telegram(
+                    &mut oc,
+                    sci_telegram.clone(),
+                    &mut telegram_state,
+                    io_cfg.clone(),
+                    &sci_cfg,
+                );
+                if telegram_allowed(&sci_telegram.message_type, &state) {
+                    assert!(result.is_ok());
+                } else {
+                    assert_eq!(
+                        result.map_err(|e| e.close_reason()).err(),
+                        Some(SCICloseReason::ProtocolError)
+                    );
+                    assert_eq!(telegram_state, state);
+                }
+            }
         }
     }
 
-    // fallback when connection is interrupted
-    {
-        let mut locked_oc = main_lock_oc.write().unwrap();
-        locked_oc.show_signal_aspect(most_restrictive_aspect, io_cfg.clone());
+    // passes the packets of the test to the receiver and the sent telegrams back together with
+    // the time they left the receiver, like a transport that sends at once
+    struct MockTransport {
+        packets: Option<mpsc::UnboundedReceiver<Vec<u8>>>,
+        sent: mpsc::UnboundedSender<(Instant, SCITelegram)>,
     }
 
-    //stop signal checks
-    check_thread.stop();
-    Ok(())
+    #[tonic::async_trait]
+    impl SciTransport for MockTransport {
+        fn name(&self) -> String {
+            "the mock transport".to_string()
+        }
+
+        async fn connect(&mut self) -> Result<SciLink, TransportError> {
+            let incoming = self.packets.take().ok_or("ALREADY CONNECTED")?;
+            let (outgoing, mut outgoing_receiver) = mpsc::unbounded_channel::<SCITelegram>();
+            let (flushed_sender, flushed) = oneshot::channel();
+            let sent = self.sent.clone();
+            tokio::spawn(async move {
+                while let Some(sci_telegram) = outgoing_receiver.recv().await {
+                    let _ = sent.send((Instant::now(), sci_telegram));
+                }
+                let _ = flushed_sender.send(());
+            });
+            Ok(SciLink {
+                outgoing,
+                incoming,
+                flushed,
+            })
+        }
+    }
+
+    // run with --nocapture to see the time from the status request to the status finish
+    #[tokio::test]
+    async fn status_request_is_answered_at_once() {
+        let io_cfg = PinConfig::default();
+        let sci_cfg = test_sci_config();
+        let shared = SharedState::new(test_oc(&io_cfg), io_cfg, ConfigReload::new(None));
+        let (packets, incoming) = mpsc::unbounded_channel();
+        let (sent, mut sent_receiver) = mpsc::unbounded_channel();
+        let mut transport = MockTransport {
+            packets: Some(incoming),
+            sent,
+        };
+        let ls = ProtocolType::SCIProtocolLS;
+
+        let interlocking = async {
+            packets
+                .send(SCITelegram::version_request(ls, "C", "S", SCI_LS_VERSION).into())
+                .unwrap();
+            let (_, version_response) = sent_receiver.recv().await.unwrap();
+            assert_eq!(
+                version_response.message_type,
+                SCIMessageType::sci_version_response()
+            );
+
+            let requested = Instant::now();
+            packets
+                .send(SCITelegram::status_request(ls, "C", "S").into())
+                .unwrap();
+            let mut message_types = Vec::new();
+            let mut finished = None;
+            while finished.is_none() {
+                let (sent_at, sci_telegram) = sent_receiver.recv().await.unwrap();
+                if sci_telegram.message_type == SCIMessageType::sci_status_finish() {
+                    finished = Some(sent_at.duration_since(requested));
+                }
+                message_types.push(sci_telegram.message_type);
+            }
+            packets
+                .send(SCITelegram::close(ls, "C", "S", SCICloseReason::NormalClose).into())
+                .unwrap();
+            (message_types, finished.unwrap())
+        };
+
+        let mut handshake_completed = false;
+        let (connection, (message_types, latency)) = tokio::join!(
+            run_connection(&mut transport, &shared, &sci_cfg, &mut handshake_completed),
+            interlocking
+        );
+        println!("Status request to status finish: {:?}", latency);
+        assert!(connection.is_ok());
+        assert!(handshake_completed);
+        assert_eq!(
+            message_types,
+            vec![
+                SCIMessageType::sci_status_begin(),
+                SCIMessageType::scils_signal_aspect_status(),
+                SCIMessageType::scils_brightness_status(),
+                SCIMessageType::sci_status_finish(),
+            ]
+        );
+        // the former send queue took 500 ms per telegram
+        assert!(latency < Duration::from_millis(100));
+    }
 }
@@ -1,118 +1,602 @@
-use crate::io_config::PinConfig;
+use crate::blink::BlinkDriver;
+use crate::io_backend::{IOBackend, PinAddress, SharedIOBackend};
+use crate::io_config::{
+    aspect_output_pins, indicator_pins, signal_from_name, signal_name, IndicatorConfig, PinConfig,
+    TransitionMode, UnexpectedLampReaction,
+};
 use chrono::Local;
-use picontrol::bindings::SPIValue;
-use picontrol::PiControl;
-use sci_rs::scils::{SCILSBrightness, SCILSMain, SCILSSignalAspect};
-use std::collections::HashMap;
+use sci_rs::scils::{
+    SCILSBrightness, SCILSDarkSwitching, SCILSMain, SCILSSignalAspect, SCILSZs2, SCILSZs3,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// time a lamp needs after switching a line until its input shows the new state
+const RETEST_SETTLE_MS: u64 = 100;
 
 pub struct OC {
     pub main_aspect: SCILSMain,
     pub main_aspect_string: String,
+    pub zs3: SCILSZs3,
+    pub zs3v: SCILSZs3,
+    pub zs2: SCILSZs2,
+    pub zs2v: SCILSZs2,
+    pub dark_switching: SCILSDarkSwitching,
+    pub nationally_specified_information: [u8; 9],
     pub backup_map: HashMap<String, String>,
+    // lamp indices running on their backup line
+    pub backup_lamps: HashSet<usize>,
+    // lamp indices without input on both lines, i.e. redundancy is exhausted
+    pub failed_lamps: HashSet<usize>,
+    // lamp indices lit in the shown aspect
+    pub lit_lamps: HashSet<usize>,
+    // lamp indices with a lit input although they should be dark
+    pub unexpected_lamps: HashSet<usize>,
+    // the lamp power was cut through the master pin
+    pub power_cut: bool,
+    // number of switches to the backup line per input pin
+    pub failover_counts: HashMap<String, u32>,
+    pub last_primary_retest: Instant,
     pub brightness: SCILSBrightness,
+    pub io: SharedIOBackend,
+    pub blink: Option<BlinkDriver>,
+    // resolved on the first signal check
+    pub input_image: Option<InputImage>,
 }
 
-fn show_signal_aspect_internal(oc: &mut OC, signal: &str, cfg: PinConfig) {
-    println!("Signal shows {}", signal);
-    oc.main_aspect_string = signal.to_string();
-    if cfg.signals.contains_key(signal) {
-        let led_values = cfg.signals.get(signal).unwrap();
-        let mut pc = PiControl::new().unwrap();
-        for (index, value) in led_values.iter().enumerate() {
-            let pin = cfg.pins_output.get(index).unwrap();
-            set_pin_value(&mut pc, value, &pin);
+// the input pins resolved to their process image bits, read with a single access per check
+pub struct InputImage {
+    pins: Vec<Option<PinAddress>>,
+    address: u16,
+    length: usize,
+}
+
+impl InputImage {
+    pub fn new(io: &mut dyn IOBackend, pins_input: &[String]) -> Self {
+        let pins: Vec<Option<PinAddress>> = pins_input
+            .iter()
+            .map(|pin| {
+                let pin_address = io.resolve_pin(pin);
+                if pin_address.is_none() {
+                    eprintln!("INPUT PIN {} NOT FOUND IN THE PROCESS IMAGE", pin);
+                }
+                pin_address
+            })
+            .collect();
+        let addresses = pins.iter().flatten().map(|pin_address| pin_address.address);
+        let address = addresses.clone().min().unwrap_or(0);
+        let length = match addresses.max() {
+            Some(max_address) => (max_address - address) as usize + 1,
+            None => 0,
+        };
+        Self {
+            pins,
+            address,
+            length,
         }
+    }
+
+    // value of every input pin, in the order of pins_input
+    pub fn read(&self, io: &mut dyn IOBackend) -> Vec<u8> {
+        let image = io.read_image(self.address, self.length);
+        self.evaluate(&image)
+    }
+
+    // unresolved pins and pins outside of the image read as 0
+    pub fn evaluate(&self, image: &[u8]) -> Vec<u8> {
+        self.pins
+            .iter()
+            .map(|pin_address| match pin_address {
+                Some(pin_address) => pin_address
+                    .address
+                    .checked_sub(self.address)
+                    .and_then(|offset| image.get(offset as usize))
+                    .map_or(0, |byte| (byte >> pin_address.bit) & 1),
+                None => 0,
+            })
+            .collect()
+    }
+}
+
+// bitmask of the first eight lamps, used in the nationally specified information
+fn lamp_mask(lamps: &HashSet<usize>) -> u8 {
+    lamps
+        .iter()
+        .filter(|index| **index < 8)
+        .fold(0, |mask, index| mask | (1 << index))
+}
+
+// output pin currently driving the lamp at the given index and the other, unused line
+fn lamp_lines<'a>(oc: &OC, index: usize, cfg: &'a PinConfig) -> (&'a str, &'a str) {
+    let primary = &cfg.pins_output[index];
+    let backup = &cfg.pins_output_backup[index];
+    if oc.backup_map.contains_key(&cfg.pins_input[index]) {
+        (backup, primary)
     } else {
-        eprintln!("NO CONFIG FOUND FOR SCI SIGNAL {}", signal)
+        (primary, backup)
+    }
+}
+
+// adds the lamp on its active line to the outputs and switches the other line off
+fn add_lamp_value(outputs: &mut Vec<(String, u8)>, value: &u8, lines: (&str, &str)) {
+    let (active, inactive) = lines;
+    if inactive != active {
+        add_pin_value(outputs, &0, inactive);
+    }
+    add_pin_value(outputs, value, active);
+}
+
+// switches both lines of a lamp in one process image update
+fn set_lamp_value(io: &mut dyn IOBackend, value: &u8, lines: (&str, &str)) {
+    let mut outputs = Vec::new();
+    add_lamp_value(&mut outputs, value, lines);
+    io.write_outputs(&outputs);
+}
+
+// lamp patterns shown before the pattern of the new aspect and the time each of them is shown
+fn transition_steps(oc: &OC, main: SCILSMain, cfg: &PinConfig) -> (Vec<Vec<u8>>, Duration) {
+    let signal = signal_name(main);
+    let led_values = cfg.signals.get(main).unwrap();
+    let rule = cfg.transitions.iter().find(|rule| {
+        (rule.from == oc.main_aspect_string || rule.from == "*")
+            && (rule.to == signal || rule.to == "*")
+    });
+    if let Some(rule) = rule.filter(|rule| !rule.intermediate.is_empty()) {
+        return (
+            rule.intermediate.clone(),
+            Duration::from_millis(rule.dwell_time),
+        );
+    }
+    let (mode, dwell_time) = match rule {
+        Some(rule) => (rule.mode.unwrap_or(cfg.transition_mode), rule.dwell_time),
+        None => (cfg.transition_mode, cfg.transition_dwell_time),
+    };
+    if mode == TransitionMode::Direct {
+        return (Vec::new(), Duration::ZERO);
+    }
+    // lamps of the most restrictive aspect go on first, permissive lamps of the new aspect last
+    let restrictive = cfg
+        .signals
+        .get_by_name(&cfg.most_restrictive_aspect)
+        .cloned()
+        .unwrap_or_default();
+    let lit = |index: usize| oc.lit_lamps.contains(&index);
+    let is_restrictive = |index: usize| restrictive.get(index) == Some(&1);
+    let restrictive_on: Vec<u8> = (0..led_values.len())
+        .map(|index| (lit(index) || (led_values[index] == 1 && is_restrictive(index))) as u8)
+        .collect();
+    let old_off: Vec<u8> = (0..led_values.len())
+        .map(|index| (led_values[index] == 1 && (lit(index) || is_restrictive(index))) as u8)
+        .collect();
+    let mut steps: Vec<Vec<u8>> = Vec::new();
+    for step in [restrictive_on, old_off] {
+        let previous_lit = match steps.last() {
+            Some(previous) => previous.iter().map(|value| *value == 1).collect(),
+            None => (0..led_values.len()).map(lit).collect::<Vec<bool>>(),
+        };
+        let unchanged = step
+            .iter()
+            .zip(previous_lit.iter())
+            .all(|(value, previous)| (*value == 1) == *previous);
+        if !unchanged && step != *led_values {
+            steps.push(step);
+        }
+    }
+    (steps, Duration::from_millis(dwell_time))
+}
+
+// the lamps of the aspect are written together with the given outputs (e.g. the indicators),
+// so the signal never shows a mix of the previous and the new aspect
+fn show_signal_aspect_internal(
+    oc: &mut OC,
+    main: SCILSMain,
+    cfg: &PinConfig,
+    mut outputs: Vec<(String, u8)>,
+) {
+    let signal = signal_name(main);
+    let started = Instant::now();
+    println!("Signal shows {}", signal);
+    // the previous aspect must not toggle any lamp of the new one
+    if let Some(blink) = oc.blink.take() {
+        blink.stop();
+    }
+    let (steps, dwell_time) = transition_steps(oc, main, cfg);
+    for step in steps {
+        println!("Transition to {} shows {:?}", signal, step);
+        let mut step_outputs = Vec::new();
+        for (index, value) in step.iter().enumerate() {
+            add_lamp_value(&mut step_outputs, value, lamp_lines(oc, index, cfg));
+        }
+        oc.io.lock().unwrap().write_outputs(&step_outputs);
+        // the validation keeps the whole transition within MAX_TRANSITION_MS
+        thread::sleep(dwell_time);
+    }
+    oc.main_aspect_string = signal.to_string();
+    let led_values = cfg.signals.get(main).unwrap();
+    oc.lit_lamps = (0..led_values.len())
+        .filter(|index| led_values[*index] == 1)
+        .collect();
+    for (index, value) in led_values.iter().enumerate() {
+        add_lamp_value(&mut outputs, value, lamp_lines(oc, index, cfg));
+    }
+    oc.io.lock().unwrap().write_outputs(&outputs);
+    println!("Aspect change to {} took {:?}", signal, started.elapsed());
+    start_blink(oc, signal, cfg);
+}
+
+// the blink driver toggles the active line of each flashing lamp
+fn start_blink(oc: &mut OC, signal: &str, cfg: &PinConfig) {
+    let led_values = cfg.signals.get_by_name(signal).unwrap();
+    if let Some(flashing) = cfg.flashing.get(signal) {
+        let pins: Vec<(usize, String)> = flashing
+            .pins
+            .iter()
+            .enumerate()
+            .filter(|(index, blink)| **blink == 1 && led_values.get(*index) == Some(&1))
+            .map(|(index, _)| (index, lamp_lines(oc, index, cfg).0.to_string()))
+            .collect();
+        println!(
+            "Signal {} flashes at {} Hz with duty cycle {}",
+            signal, flashing.frequency, flashing.duty_cycle
+        );
+        oc.blink = Some(BlinkDriver::start(
+            oc.io.clone(),
+            pins,
+            flashing.frequency,
+            flashing.duty_cycle,
+        ));
     }
 }
 
 // searches for the given pin and sets the given value
-fn set_pin_value(pc: &mut PiControl, value: &u8, pin: &&String) {
+fn set_pin_value(io: &mut dyn IOBackend, value: &u8, pin: &str) {
+    println!("PIN: {}, VALUE: {}", pin, value);
+    io.write_output(pin, *value);
+}
+
+// collects the value of a pin for the next process image update
+fn add_pin_value(outputs: &mut Vec<(String, u8)>, value: &u8, pin: &str) {
     println!("PIN: {}, VALUE: {}", pin, value);
+    outputs.push((pin.to_string(), *value));
+}
+
+// every pin the OC writes
+fn output_pins(cfg: &PinConfig) -> impl Iterator<Item = &String> {
+    aspect_output_pins(cfg)
+        .chain(std::iter::once(&cfg.day_night_pin))
+        .chain(cfg.master_pin.iter())
+}
 
-    let var_data = pc.find_variable(&pin);
-    let mut val = SPIValue {
-        i16uAddress: var_data.i16uAddress,
-        i8uBit: var_data.i8uBit,
-        i8uValue: *value,
+// shows the pattern configured for the value of an indicator (Zs3, Zs3v, Zs2, Zs2v) and returns
+// the value that is actually displayed, indicators without config or pattern stay dark
+fn show_indicator<T: Debug + Default>(
+    outputs: &mut Vec<(String, u8)>,
+    name: &str,
+    indicator_cfg: &Option<IndicatorConfig>,
+    value: T,
+) -> T {
+    let indicator_cfg = match indicator_cfg {
+        Some(indicator_cfg) => indicator_cfg,
+        None => return T::default(),
     };
-    pc.set_bit_value(&mut val);
+    let key = format!("{:?}", value);
+    let (pattern, displayed) = match indicator_cfg.signals.get(&key) {
+        Some(pattern) => (pattern.clone(), value),
+        None => {
+            eprintln!("NO CONFIG FOUND FOR {} {}, SWITCHING IT OFF", name, key);
+            (vec![0; indicator_cfg.pins.len()], T::default())
+        }
+    };
+    println!("{} shows {:?}", name, displayed);
+    for (index, value) in pattern.iter().enumerate() {
+        add_pin_value(outputs, value, &indicator_cfg.pins[index]);
+    }
+    displayed
+}
+
+pub fn most_restrictive_aspect(cfg: &PinConfig) -> SCILSSignalAspect {
+    let main = signal_from_name(&cfg.most_restrictive_aspect).unwrap_or(SCILSMain::Off);
+    SCILSSignalAspect::new(
+        main,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        [0u8; 9],
+    )
 }
 
 impl OC {
+    pub fn new(io: SharedIOBackend) -> Self {
+        OC {
+            main_aspect: Default::default(),
+            main_aspect_string: "Off".to_string(),
+            zs3: Default::default(),
+            zs3v: Default::default(),
+            zs2: Default::default(),
+            zs2v: Default::default(),
+            dark_switching: SCILSDarkSwitching::Show,
+            nationally_specified_information: [0u8; 9],
+            backup_map: HashMap::new(),
+            backup_lamps: HashSet::new(),
+            failed_lamps: HashSet::new(),
+            lit_lamps: HashSet::new(),
+            unexpected_lamps: HashSet::new(),
+            power_cut: false,
+            failover_counts: HashMap::new(),
+            last_primary_retest: Instant::now(),
+            brightness: SCILSBrightness::Day,
+            io,
+            blink: None,
+            input_image: None,
+        }
+    }
+
+    // aspects without a pattern in the pin config are rejected, the signal then shows
+    // the most restrictive aspect and reports it instead of the commanded one
     pub fn show_signal_aspect(&mut self, signal_aspect: SCILSSignalAspect, cfg: PinConfig) {
-        match signal_aspect.main() {
-            SCILSMain::Hp0 => show_signal_aspect_internal(self, "Hp0", cfg),
-            SCILSMain::Hp0PlusSh1 => show_signal_aspect_internal(self, "Hp0PlusSh1", cfg),
-            SCILSMain::Hp0WithDrivingIndicator => {}
-            SCILSMain::Ks1 => show_signal_aspect_internal(self, "Ks1", cfg),
-            SCILSMain::Ks1Flashing => show_signal_aspect_internal(self, "Ks1Flashing", cfg),
-            SCILSMain::Ks1FlashingWithAdditionalLight => {
-                show_signal_aspect_internal(self, "Ks1Flashing", cfg)
+        self.nationally_specified_information = signal_aspect.nationally_specified_information();
+        if signal_aspect.dark_switching() == SCILSDarkSwitching::Dark && cfg.dark_switching {
+            println!("Signal is switched dark");
+            self.switch_off(&cfg);
+            self.main_aspect = signal_aspect.main();
+            self.dark_switching = SCILSDarkSwitching::Dark;
+            return;
+        }
+        self.dark_switching = SCILSDarkSwitching::Show;
+        let signal = signal_name(signal_aspect.main());
+        if let Some(substitute) = self.degraded_substitute(signal, &cfg) {
+            println!(
+                "LAMP OF {} FAILED ON BOTH LINES - SHOWING {} INSTEAD",
+                signal, substitute
+            );
+            self.show_without_indicators(&substitute, &cfg);
+        } else if cfg.signals.contains(signal_aspect.main()) {
+            let outputs = self.show_indicators(&signal_aspect, &cfg);
+            show_signal_aspect_internal(self, signal_aspect.main(), &cfg, outputs);
+            self.main_aspect = signal_aspect.main();
+        } else {
+            eprintln!(
+                "NO CONFIG FOUND FOR SCI SIGNAL {}, REJECTED - FALLING BACK TO {}",
+                signal, cfg.most_restrictive_aspect
+            );
+            self.show_fallback_aspect(&cfg);
+        }
+    }
+
+    // returns the indicator outputs, they are written together with the main aspect
+    fn show_indicators(
+        &mut self,
+        signal_aspect: &SCILSSignalAspect,
+        cfg: &PinConfig,
+    ) -> Vec<(String, u8)> {
+        let mut outputs = Vec::new();
+        self.zs3 = show_indicator(&mut outputs, "Zs3", &cfg.zs3, signal_aspect.zs3());
+        self.zs3v = show_indicator(&mut outputs, "Zs3v", &cfg.zs3v, signal_aspect.zs3v());
+        self.zs2 = show_indicator(&mut outputs, "Zs2", &cfg.zs2, signal_aspect.zs2());
+        self.zs2v = show_indicator(&mut outputs, "Zs2v", &cfg.zs2v, signal_aspect.zs2v());
+        outputs
+    }
+
+    fn reset_indicators(&mut self) {
+        self.zs3 = Default::default();
+        self.zs3v = Default::default();
+        self.zs2 = Default::default();
+        self.zs2v = Default::default();
+    }
+
+    // switches all lamps including the backup lines and indicators off
+    fn switch_off(&mut self, cfg: &PinConfig) {
+        if let Some(blink) = self.blink.take() {
+            blink.stop();
+        }
+        let mut outputs = Vec::new();
+        for pin in cfg
+            .pins_output
+            .iter()
+            .chain(cfg.pins_output_backup.iter())
+            .chain(indicator_pins(cfg))
+        {
+            add_pin_value(&mut outputs, &0, pin);
+        }
+        self.io.lock().unwrap().write_outputs(&outputs);
+        self.main_aspect_string = signal_name(SCILSMain::Off).to_string();
+        self.lit_lamps.clear();
+        self.reset_indicators();
+    }
+
+    // the indicators are switched off together with the rejected aspect
+    fn show_fallback_aspect(&mut self, cfg: &PinConfig) {
+        let fallback = signal_from_name(&cfg.most_restrictive_aspect);
+        match fallback {
+            Some(main) if cfg.signals.contains(main) => {
+                match self.degraded_substitute(signal_name(main), cfg) {
+                    Some(substitute) => self.show_without_indicators(&substitute, cfg),
+                    None => self.show_without_indicators(signal_name(main), cfg),
+                }
             }
-            SCILSMain::Ks2 => show_signal_aspect_internal(self, "Ks2", cfg),
-            SCILSMain::Ks2WithAdditionalLight => {
-                show_signal_aspect_internal(self, "Ks2WithAdditionalLight", cfg)
+            _ => {
+                self.switch_off(cfg);
+                eprintln!(
+                    "NO CONFIG FOUND FOR MOST RESTRICTIVE SIGNAL {}, ALL LAMPS STAY OFF",
+                    cfg.most_restrictive_aspect
+                );
+                self.main_aspect = SCILSMain::Off;
             }
-            SCILSMain::Sh1 => show_signal_aspect_internal(self, "Sh1", cfg),
-            SCILSMain::IdLight => show_signal_aspect_internal(self, "IdLight", cfg),
-            SCILSMain::Hp0Hv => show_signal_aspect_internal(self, "Hp0Hv", cfg),
-            SCILSMain::Hp1 => show_signal_aspect_internal(self, "Hp1", cfg),
-            SCILSMain::Hp2 => show_signal_aspect_internal(self, "Hp2", cfg),
-            SCILSMain::Vr0 => show_signal_aspect_internal(self, "Vr0", cfg),
-            SCILSMain::Vr1 => show_signal_aspect_internal(self, "Vr1", cfg),
-            SCILSMain::Vr2 => show_signal_aspect_internal(self, "Vr2", cfg),
-            SCILSMain::Off => show_signal_aspect_internal(self, "Off", cfg),
-        }
-        self.main_aspect = signal_aspect.main();
-    }
-
-    pub fn signal_aspect_status(&self) -> SCILSSignalAspect {
-        let nationally_specified_information = [0u8; 9];
-        let signal_aspect = SCILSSignalAspect::new(
-            self.main_aspect,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Default::default(),
+        }
+    }
+
+    // shows the given aspect with all indicators off, "Off" and aspects without a pattern switch
+    // the signal dark
+    fn show_without_indicators(&mut self, signal: &str, cfg: &PinConfig) {
+        match signal_from_name(signal) {
+            Some(main) if main != SCILSMain::Off && cfg.signals.contains(main) => {
+                let mut outputs = Vec::new();
+                for pin in indicator_pins(cfg) {
+                    add_pin_value(&mut outputs, &0, pin);
+                }
+                self.reset_indicators();
+                show_signal_aspect_internal(self, main, cfg, outputs);
+                self.main_aspect = main;
+            }
+            _ => {
+                println!("Signal is switched dark");
+                self.switch_off(cfg);
+                self.main_aspect = SCILSMain::Off;
+            }
+        }
+    }
+
+    // first entry of degraded_aspects for the given aspect that needs none of the failed lamps,
+    // None if the aspect needs no failed lamp or no such substitute is configured
+    fn degraded_substitute(&self, signal: &str, cfg: &PinConfig) -> Option<String> {
+        let needs_failed_lamp = |name: &str| match cfg.signals.get_by_name(name) {
+            Some(pattern) => pattern
+                .iter()
+                .enumerate()
+                .any(|(index, value)| *value == 1 && self.failed_lamps.contains(&index)),
+            None => name != signal_name(SCILSMain::Off),
+        };
+        if !needs_failed_lamp(signal) {
+            return None;
+        }
+        cfg.degraded_aspects
+            .get(signal)?
+            .iter()
+            .find(|substitute| !needs_failed_lamp(substitute))
+            .cloned()
+    }
+
+    // replaces the shown aspect if one of its lamps failed on both lines
+    fn show_degraded_aspect(&mut self, cfg: &PinConfig) {
+        let signal = self.main_aspect_string.clone();
+        if let Some(substitute) = self.degraded_substitute(&signal, cfg) {
+            println!(
+                "{} LAMP OF {} FAILED ON BOTH LINES - SHOWING {} INSTEAD",
+                Local::now().format("%d-%m-%Y %H:%M:%S"),
+                signal,
+                substitute
+            );
+            self.show_without_indicators(&substitute, cfg);
+        }
+    }
+
+    // reports what the signal actually displays: a lamp of the shown aspect that failed on both
+    // lines, a lit lamp that should be dark or cut lamp power make the aspect unreliable, so it is
+    // reported as Off. The nationally specified information is echoed, with lamp_status_bytes its
+    // first three bytes carry the lamps on their backup line, the failed lamps and the
+    // unexpectedly lit lamps instead.
+    pub fn signal_aspect_status(&self, cfg: &PinConfig) -> SCILSSignalAspect {
+        let main_aspect = if self.lit_lamps.is_disjoint(&self.failed_lamps)
+            && self.unexpected_lamps.is_empty()
+            && !self.power_cut
+        {
+            self.main_aspect
+        } else {
+            SCILSMain::Off
+        };
+        let mut nationally_specified_information = self.nationally_specified_information;
+        if cfg.lamp_status_bytes {
+            nationally_specified_information[0] = lamp_mask(&self.backup_lamps);
+            nationally_specified_information[1] = lamp_mask(&self.failed_lamps);
+            nationally_specified_information[2] = lamp_mask(&self.unexpected_lamps);
+        }
+        SCILSSignalAspect::new(
+            main_aspect,
             Default::default(),
+            self.zs3,
+            self.zs3v,
+            self.zs2,
+            self.zs2v,
             Default::default(),
             Default::default(),
             Default::default(),
+            self.dark_switching,
             nationally_specified_information,
-        );
-        signal_aspect
+        )
     }
 
-    pub fn check_signal(&mut self, cfg: &PinConfig) {
+    // returns true if a lamp switched to its backup line or failed on both lines since the last
+    // check, so the interlocking can be informed
+    pub fn check_signal(&mut self, cfg: &PinConfig) -> bool {
+        let backup_lamps = self.backup_lamps.clone();
+        let failed_lamps = self.failed_lamps.clone();
+        let unexpected_lamps = self.unexpected_lamps.clone();
+        let main_aspect = self.main_aspect;
+        let power_cut = self.power_cut;
         let signal = self.main_aspect_string.clone();
         println!("___________________________________________________");
         println!("Check signal {}", signal);
-        if cfg.signals.contains_key(&*signal) {
-            let led_values = cfg.signals.get(&*signal).unwrap();
-            let mut pc = PiControl::new().unwrap();
+        if let Some(led_values) = cfg.signals.get_by_name(&signal) {
+            let mut locked_io = self.io.lock().unwrap();
+            let input_image = self
+                .input_image
+                .get_or_insert_with(|| InputImage::new(&mut *locked_io, &cfg.pins_input));
+            let inputs = input_image.read(&mut *locked_io);
             let mut error_found = false;
+            let mut blink_rerouted = false;
 
             for (index, value) in led_values.iter().enumerate() {
                 let pin = cfg.pins_input.get(index).unwrap();
-                let var_data = pc.find_variable(&pin);
-                let res = pc.read(var_data.i16uAddress.into(), 1);
-                if res.iter().all(|&v| v == 0) && *value == 1 {
+                // a flashing lamp in its off phase is expected to be dark
+                let in_off_phase = match &self.blink {
+                    Some(blink) => !blink.is_lit(index),
+                    None => false,
+                };
+                if in_off_phase {
+                    continue;
+                }
+                let input = inputs[index];
+                // without lamp power, every lamp is expected to be dark
+                let value = if self.power_cut { &0 } else { value };
+                if input == 1 && *value == 1 {
+                    self.failed_lamps.remove(&index);
+                }
+                // without lamp power, an unexpectedly lit lamp cannot be verified again
+                if input == 0 && *value == 0 && !self.power_cut {
+                    self.unexpected_lamps.remove(&index);
+                }
+                if input == 1 && *value == 0 {
+                    println!(
+                        "{} ERROR: INPUT SIGNAL FOUND AT {} ALTHOUGH THE LAMP SHOULD BE DARK!",
+                        Local::now().format("%d-%m-%Y %H:%M:%S"),
+                        pin
+                    );
+                    self.unexpected_lamps.insert(index);
+                    error_found = true;
+                }
+                if input == 0 && *value == 1 {
                     if self.backup_map.contains_key(pin) {
                         println!("{} WARN: NO INPUT SIGNAL FOUND AT {}, BACKUP LINE ALREADY ACTIVE ON {}",
-                                 Local::now().format("%d-%m-%Y %H:%M:%S").to_string(), pin, self.backup_map.get(pin).unwrap());
+                                 Local::now().format("%d-%m-%Y %H:%M:%S"), pin, self.backup_map.get(pin).unwrap());
+                        self.failed_lamps.insert(index);
                     } else {
                         println!(
                             "{} ERROR: NO INPUT SIGNAL FOUND AT {}, TRYING TO USE THE BACKUP LINE!",
-                            Local::now().format("%d-%m-%Y %H:%M:%S").to_string(),
+                            Local::now().format("%d-%m-%Y %H:%M:%S"),
                             pin
                         );
                         let backup_pin = cfg.pins_output_backup.get(index).unwrap();
-                        set_pin_value(&mut pc, value, &backup_pin);
+                        let primary_pin = cfg.pins_output.get(index).unwrap();
+                        set_lamp_value(&mut *locked_io, value, (backup_pin, primary_pin));
                         self.backup_map
                             .insert(pin.to_string(), backup_pin.to_string());
+                        if self
+                            .blink
+                            .as_ref()
+                            .is_some_and(|blink| blink.is_flashing(index))
+                        {
+                            blink_rerouted = true;
+                        }
+                        self.backup_lamps.insert(index);
+                        *self.failover_counts.entry(pin.to_string()).or_insert(0) += 1;
                     }
                     error_found = true;
                 }
@@ -120,11 +604,165 @@ impl OC {
             if !error_found {
                 println!(
                     "{} Signal OK! No errors found.",
-                    Local::now().format("%d-%m-%Y %H:%M:%S").to_string()
+                    Local::now().format("%d-%m-%Y %H:%M:%S")
                 );
             }
+            if !self.failover_counts.is_empty() {
+                println!("Failovers per pin: {:?}", self.failover_counts);
+            }
             println!("___________________________________________________");
+            // the blink driver has to toggle the backup line from now on
+            drop(locked_io);
+            if blink_rerouted {
+                if let Some(blink) = self.blink.take() {
+                    blink.stop();
+                }
+                start_blink(self, &signal, cfg);
+            }
+            if !self.lit_lamps.is_disjoint(&self.failed_lamps) {
+                self.show_degraded_aspect(cfg);
+            }
+        }
+        let mut report = false;
+        if !self.unexpected_lamps.is_subset(&unexpected_lamps) {
+            report = self.react_to_unexpected_lamps(cfg);
+        }
+        self.backup_lamps != backup_lamps
+            || self.failed_lamps != failed_lamps
+            || (report && self.unexpected_lamps != unexpected_lamps)
+            || self.main_aspect != main_aspect
+            || self.power_cut != power_cut
+    }
+
+    // runs the configured reactions to a newly detected lit lamp that should be dark,
+    // returns true if the interlocking has to be informed
+    fn react_to_unexpected_lamps(&mut self, cfg: &PinConfig) -> bool {
+        let mut report = false;
+        for reaction in cfg.unexpected_lamp_reactions.iter() {
+            match reaction {
+                UnexpectedLampReaction::MostRestrictive => {
+                    if signal_name(self.main_aspect) != cfg.most_restrictive_aspect {
+                        println!(
+                            "UNEXPECTED LIT LAMP - FALLING BACK TO {}",
+                            cfg.most_restrictive_aspect
+                        );
+                        self.show_fallback_aspect(cfg);
+                    }
+                }
+                UnexpectedLampReaction::Report => report = true,
+                UnexpectedLampReaction::CutPower => {
+                    println!("UNEXPECTED LIT LAMP - CUTTING LAMP POWER");
+                    self.set_power(false, cfg);
+                }
+            }
         }
+        report
+    }
+
+    // switches the lamp power through the master pin, if one is configured
+    pub fn set_power(&mut self, on: bool, cfg: &PinConfig) {
+        let master_pin = match &cfg.master_pin {
+            Some(master_pin) => master_pin,
+            None => {
+                if !on {
+                    eprintln!("NO MASTER PIN CONFIGURED, CANNOT CUT LAMP POWER");
+                }
+                return;
+            }
+        };
+        let mut locked_io = self.io.lock().unwrap();
+        set_pin_value(&mut *locked_io, &(on as u8), master_pin);
+        self.power_cut = !on;
+    }
+
+    // switches the backup line of the lamp at the given input pin off and keeps it off if the
+    // primary line alone lights the lamp, otherwise the backup line is switched on again. Only a
+    // lamp lit in the shown aspect can be verified, a dark lamp stays on its backup line.
+    pub fn retest_primary_line(&mut self, input_pin: &str, cfg: &PinConfig) -> bool {
+        let index = match cfg.pins_input.iter().position(|pin| pin == input_pin) {
+            Some(index) => index,
+            None => {
+                eprintln!("NO INPUT PIN {} IN THE PIN CONFIG", input_pin);
+                return false;
+            }
+        };
+        let backup_pin = match self.backup_map.get(input_pin) {
+            Some(backup_pin) => backup_pin.to_string(),
+            None => {
+                println!("BACKUP LINE OF {} IS NOT ACTIVE", input_pin);
+                return false;
+            }
+        };
+        if self
+            .blink
+            .as_ref()
+            .is_some_and(|blink| blink.is_flashing(index))
+        {
+            println!(
+                "LAMP OF {} IS FLASHING, RETEST IT WHILE A STEADY ASPECT IS SHOWN",
+                input_pin
+            );
+            return false;
+        }
+        if !self.lit_lamps.contains(&index) {
+            println!(
+                "LAMP OF {} IS DARK IN THE SHOWN ASPECT, RETEST IT WHILE IT IS LIT",
+                input_pin
+            );
+            return false;
+        }
+        let primary_pin = cfg.pins_output[index].as_str();
+        let mut locked_io = self.io.lock().unwrap();
+        set_lamp_value(&mut *locked_io, &1, (primary_pin, &backup_pin));
+        thread::sleep(Duration::from_millis(RETEST_SETTLE_MS));
+        if locked_io.read_input(input_pin) == 0 {
+            println!(
+                "{} PRIMARY LINE OF {} STILL FAILED, KEEPING BACKUP LINE {}",
+                Local::now().format("%d-%m-%Y %H:%M:%S"),
+                input_pin,
+                backup_pin
+            );
+            set_lamp_value(&mut *locked_io, &1, (&backup_pin, primary_pin));
+            return false;
+        }
+        println!(
+            "{} SWITCHED {} BACK TO THE PRIMARY LINE, BACKUP LINE {} IS OFF",
+            Local::now().format("%d-%m-%Y %H:%M:%S"),
+            input_pin,
+            backup_pin
+        );
+        self.backup_map.remove(input_pin);
+        self.backup_lamps.remove(&index);
+        self.failed_lamps.remove(&index);
+        true
+    }
+
+    // re-tests the primary lines in use of a backup line whose lamps are lit, only while the signal
+    // shows the most restrictive aspect and at most once per primary_retest_interval
+    pub fn retest_primary_lines(&mut self, cfg: &PinConfig) -> bool {
+        let interval = match cfg.primary_retest_interval {
+            Some(interval) => Duration::from_secs(interval),
+            None => return false,
+        };
+        if self.backup_map.is_empty()
+            || self.blink.is_some()
+            || self.last_primary_retest.elapsed() < interval
+            || signal_name(self.main_aspect) != cfg.most_restrictive_aspect
+        {
+            return false;
+        }
+        self.last_primary_retest = Instant::now();
+        let input_pins: Vec<String> = self
+            .lit_lamps
+            .iter()
+            .map(|index| cfg.pins_input[*index].clone())
+            .filter(|input_pin| self.backup_map.contains_key(input_pin))
+            .collect();
+        let mut recovered = false;
+        for input_pin in input_pins {
+            recovered |= self.retest_primary_line(&input_pin, cfg);
+        }
+        recovered
     }
 
     pub fn change_brightness(&mut self, brightness: SCILSBrightness, cfg: PinConfig) {
@@ -135,11 +773,241 @@ impl OC {
         } else {
             1
         };
-        let pc = PiControl::new().unwrap();
-        set_pin_value(&mut pc, pin_value, &&cfg.day_night_pin);
+        let mut locked_io = self.io.lock().unwrap();
+        set_pin_value(&mut *locked_io, &pin_value, &cfg.day_night_pin);
+    }
+
+    // switches off the outputs the new config does not use and shows its most restrictive aspect,
+    // the lamp states found with the old config do not apply to the new pins
+    pub fn apply_config(&mut self, old_cfg: &PinConfig, new_cfg: &PinConfig) {
+        if let Some(blink) = self.blink.take() {
+            blink.stop();
+        }
+        let new_pins: HashSet<&String> = output_pins(new_cfg).collect();
+        let mut outputs = Vec::new();
+        for pin in output_pins(old_cfg).filter(|pin| !new_pins.contains(pin)) {
+            add_pin_value(&mut outputs, &0, pin);
+        }
+        self.io.lock().unwrap().write_outputs(&outputs);
+        self.backup_map.clear();
+        self.backup_lamps.clear();
+        self.failed_lamps.clear();
+        self.lit_lamps.clear();
+        if !self.power_cut {
+            self.unexpected_lamps.clear();
+        }
+        self.input_image = None;
+        if new_cfg.master_pin.is_some() {
+            self.set_power(!self.power_cut, new_cfg);
+        }
+        self.change_brightness(self.brightness, new_cfg.clone());
+        self.show_signal_aspect(most_restrictive_aspect(new_cfg), new_cfg.clone());
     }
 
     pub fn brightness_status(&self) -> SCILSBrightness {
         self.brightness
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn pin(address: u16, bit: u8) -> Option<PinAddress> {
+        Some(PinAddress { address, bit })
+    }
+
+    fn input_image(pins: Vec<Option<PinAddress>>, address: u16, length: usize) -> InputImage {
+        InputImage {
+            pins,
+            address,
+            length,
+        }
+    }
+
+    #[test]
+    fn evaluate_reads_pins_sharing_a_byte() {
+        let image = input_image(vec![pin(10, 0), pin(10, 3), pin(10, 7)], 10, 1);
+        assert_eq!(image.evaluate(&[0b1000_1001]), vec![1, 1, 1]);
+        assert_eq!(image.evaluate(&[0b0000_1000]), vec![0, 1, 0]);
+        assert_eq!(image.evaluate(&[0]), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn evaluate_uses_the_offset_of_the_image() {
+        let image = input_image(vec![pin(20, 1), pin(22, 6)], 20, 3);
+        assert_eq!(image.evaluate(&[0b10, 0xff, 0b100_0000]), vec![1, 1]);
+        assert_eq!(image.evaluate(&[0, 0xff, 0]), vec![0, 0]);
+    }
+
+    #[test]
+    fn evaluate_reads_bits_outside_of_the_image_as_0() {
+        let image = input_image(vec![pin(5, 0), pin(9, 0), pin(4, 0)], 5, 2);
+        // the read returned fewer bytes than requested and a pin lies before the image
+        assert_eq!(image.evaluate(&[1]), vec![1, 0, 0]);
+        assert_eq!(image.evaluate(&[]), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn evaluate_reads_unresolved_pins_as_0() {
+        let image = input_image(vec![None, pin(0, 2), None], 0, 1);
+        assert_eq!(image.evaluate(&[0xff]), vec![0, 1, 0]);
+    }
+
+    // resolves I_<bit> in byte 3 of the image, other names are unknown
+    struct FakeImage;
+
+    impl IOBackend for FakeImage {
+        fn resolve_pin(&mut self, pin: &str) -> Option<PinAddress> {
+            let bit = pin.strip_prefix("I_")?.parse().ok()?;
+            Some(PinAddress { address: 3, bit })
+        }
+        fn write_output(&mut self, _pin: &str, _value: u8) {}
+        fn read_input(&mut self, _pin: &str) -> u8 {
+            0
+        }
+        fn read_image(&mut self, address: u16, length: usize) -> Vec<u8> {
+            assert_eq!((address, length), (3, 1));
+            vec![0b0000_0101]
+        }
+        fn write_outputs(&mut self, _values: &[(String, u8)]) {}
+    }
+
+    // process image of named pins that takes ACCESS_TIME per device access and records the lamp
+    // pattern visible on the signal after every access
+    const ACCESS_TIME: Duration = Duration::from_millis(1);
+
+    struct RecordingImage {
+        values: HashMap<String, u8>,
+        lamps: Vec<String>,
+        accesses: usize,
+        visible: Vec<Vec<u8>>,
+    }
+
+    impl RecordingImage {
+        fn access(&mut self) {
+            thread::sleep(ACCESS_TIME);
+            self.accesses += 1;
+        }
+
+        fn write_access(&mut self) {
+            self.access();
+            let pattern = self
+                .lamps
+                .iter()
+                .map(|pin| *self.values.get(pin).unwrap_or(&0))
+                .collect();
+            self.visible.push(pattern);
+        }
+    }
+
+    impl IOBackend for RecordingImage {
+        fn resolve_pin(&mut self, _pin: &str) -> Option<PinAddress> {
+            None
+        }
+        fn write_output(&mut self, pin: &str, value: u8) {
+            self.values.insert(pin.to_string(), value);
+            self.write_access();
+        }
+        fn read_input(&mut self, _pin: &str) -> u8 {
+            0
+        }
+        fn read_image(&mut self, _address: u16, length: usize) -> Vec<u8> {
+            vec![0; length]
+        }
+        // like the piControl backend: one read and one write of the process image
+        fn write_outputs(&mut self, values: &[(String, u8)]) {
+            self.access();
+            for (pin, value) in values {
+                self.values.insert(pin.to_string(), *value);
+            }
+            self.write_access();
+        }
+    }
+
+    fn aspect(main: SCILSMain) -> SCILSSignalAspect {
+        SCILSSignalAspect::new(
+            main,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            [0u8; 9],
+        )
+    }
+
+    fn recording_oc(cfg: &PinConfig) -> (OC, Arc<Mutex<RecordingImage>>) {
+        let image = Arc::new(Mutex::new(RecordingImage {
+            values: HashMap::new(),
+            lamps: cfg.pins_output.clone(),
+            accesses: 0,
+            visible: Vec::new(),
+        }));
+        let mut oc = OC::new(image.clone());
+        oc.show_signal_aspect(aspect(SCILSMain::Ks2), cfg.clone());
+        let mut locked_image = image.lock().unwrap();
+        locked_image.accesses = 0;
+        locked_image.visible.clear();
+        drop(locked_image);
+        (oc, image)
+    }
+
+    // the aspect change from Ks2 to Ks1 written by a pin by pin loop in this test (one access per
+    // pin, not the old code) and by show_signal_aspect (one process image update), run with
+    // --nocapture to see the latencies
+    #[test]
+    fn aspect_change_latency_pin_by_pin_and_in_one_update() {
+        let mut cfg = PinConfig::default();
+        cfg.pins_output_backup = ["O_11", "O_12", "O_13", "O_14"]
+            .iter()
+            .map(|pin| pin.to_string())
+            .collect();
+        cfg.signals.insert(SCILSMain::Ks1, vec![0, 1, 0, 0]);
+        cfg.signals.insert(SCILSMain::Ks2, vec![0, 0, 1, 0]);
+        let ks1 = cfg.signals.get(SCILSMain::Ks1).unwrap().clone();
+
+        let (oc, image) = recording_oc(&cfg);
+        let started = Instant::now();
+        for (index, value) in ks1.iter().enumerate() {
+            let mut locked_image = image.lock().unwrap();
+            locked_image.write_output(&cfg.pins_output[index], *value);
+            locked_image.write_output(&cfg.pins_output_backup[index], 0);
+        }
+        let before = started.elapsed();
+        let before_accesses = image.lock().unwrap().accesses;
+        let before_visible = image.lock().unwrap().visible.clone();
+        drop(oc);
+
+        let (mut oc, image) = recording_oc(&cfg);
+        let started = Instant::now();
+        oc.show_signal_aspect(aspect(SCILSMain::Ks1), cfg.clone());
+        let after = started.elapsed();
+        let after_accesses = image.lock().unwrap().accesses;
+        let after_visible = image.lock().unwrap().visible.clone();
+
+        println!(
+            "Aspect change Ks2 -> Ks1: pin by pin {:?} ({} accesses), one update {:?} ({} accesses)",
+            before, before_accesses, after, after_accesses
+        );
+        // pin by pin, Ks1 and Ks2 are lit together for a moment
+        assert!(before_visible.contains(&vec![0, 1, 1, 0]));
+        assert_eq!(after_visible, vec![ks1]);
+        assert_eq!(after_accesses, 2);
+    }
+
+    #[test]
+    fn read_spans_only_the_resolved_pins() {
+        let pins_input: Vec<String> = ["I_0", "I_1", "X_2", "I_2"]
+            .iter()
+            .map(|pin| pin.to_string())
+            .collect();
+        let image = InputImage::new(&mut FakeImage, &pins_input);
+        assert_eq!(image.read(&mut FakeImage), vec![1, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,120 @@
+use config_file::FromConfigFile;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::Path;
+
+// read by the OC and the interlocking, both sides have to use the same file
+pub const SCI_CONFIG_PATH: &str = "./config/sci_config.toml";
+
+// SCI names are sent as 20 bytes in every telegram
+const SCI_NAME_MAX_LEN: usize = 20;
+
+// "grpc" connects to the rasta_grpc_bridge_udp given on the command line, "rasta" to rasta_address
+// with rasta-rs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportType {
+    #[default]
+    Grpc,
+    Rasta,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SciEndpoint {
+    pub(crate) sci_name: String,
+    pub(crate) rasta_id: u32,
+}
+
+// RASTA_T_H and RASTA_T_MAX - RASTA_T_H of config/rasta_oc.cfg
+fn default_keep_alive_interval() -> u64 {
+    2000
+}
+
+fn default_keep_alive_timeout() -> u64 {
+    8000
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SciConfig {
+    pub(crate) oc: SciEndpoint,
+    pub(crate) interlocking: SciEndpoint,
+    #[serde(default)]
+    pub(crate) transport: TransportType,
+    // RaSTA server of the interlocking the OC connects to with the rasta transport
+    pub(crate) rasta_address: SocketAddr,
+    // ms without a telegram until the OC shows the most restrictive aspect and closes the
+    // connection with TimeoutError, off unless set as interlockings send nothing while idle
+    #[serde(default)]
+    pub(crate) supervision_timeout: Option<u64>,
+    // HTTP/2 keep-alive pings to the gRPC bridge, the connection fails if a ping is not answered
+    // within keep_alive_timeout ms
+    #[serde(default = "default_keep_alive_interval")]
+    pub(crate) keep_alive_interval: u64,
+    #[serde(default = "default_keep_alive_timeout")]
+    pub(crate) keep_alive_timeout: u64,
+}
+
+fn validate_sci_config(cfg: &SciConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (side, endpoint) in [("oc", &cfg.oc), ("interlocking", &cfg.interlocking)] {
+        if endpoint.sci_name.is_empty() || endpoint.sci_name.len() > SCI_NAME_MAX_LEN {
+            problems.push(format!(
+                "SCI NAME OF {} MUST HAVE 1 TO {} BYTES",
+                side, SCI_NAME_MAX_LEN
+            ));
+        }
+    }
+    if cfg.oc.sci_name == cfg.interlocking.sci_name {
+        problems.push(format!(
+            "OC AND INTERLOCKING USE THE SAME SCI NAME {}",
+            cfg.oc.sci_name
+        ));
+    }
+    if cfg.supervision_timeout == Some(0) {
+        problems.push("SUPERVISION TIMEOUT MUST BE AT LEAST 1".to_string());
+    }
+    if cfg.keep_alive_interval == 0 || cfg.keep_alive_timeout == 0 {
+        problems.push(format!(
+            "KEEP ALIVE INTERVAL {} AND TIMEOUT {} MUST BE AT LEAST 1",
+            cfg.keep_alive_interval, cfg.keep_alive_timeout
+        ));
+    }
+    if cfg.oc.rasta_id == cfg.interlocking.rasta_id {
+        problems.push(format!(
+            "OC AND INTERLOCKING USE THE SAME RASTA ID {}",
+            cfg.oc.rasta_id
+        ));
+    }
+    problems
+}
+
+pub fn read_sci_config(config_file_path: &str) -> Result<SciConfig, String> {
+    if !Path::new(config_file_path).exists() {
+        return Err(format!("SCI CONFIG FILE {} NOT FOUND", config_file_path));
+    }
+    let cfg = SciConfig::from_config_file(config_file_path).map_err(|e| {
+        format!(
+            "COULD NOT PARSE SCI CONFIG FILE {}: {}",
+            config_file_path, e
+        )
+    })?;
+    let problems = validate_sci_config(&cfg);
+    if !problems.is_empty() {
+        return Err(format!(
+            "SCI CONFIG FILE {} IS INVALID: {}",
+            config_file_path,
+            problems.join(", ")
+        ));
+    }
+    Ok(cfg)
+}
+
+// exits if the sci config is missing or invalid, there are no default identities
+pub fn get_sci_config() -> SciConfig {
+    let cfg = read_sci_config(SCI_CONFIG_PATH).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    println!("SCI CONFIG:{:?}", cfg);
+    cfg
+}
@@ -1,5 +1,7 @@
 #![recursion_limit = "512"]
 
+mod sci_config;
+
 pub mod rasta_grpc {
     tonic::include_proto!("sci");
 }
@@ -13,6 +15,7 @@ use futures_core::Stream;
 use futures_util::StreamExt;
 use rasta_grpc::rasta_server::Rasta;
 use rasta_grpc::SciPacket;
+use sci_config::SciConfig;
 use sci_rs::scils::{SCILSBrightness, SCILSMain, SCILSSignalAspect};
 use sci_rs::{ProtocolType, SCICloseReason, SCIMessageType, SCITelegram, SCIVersionCheckResult};
 use tokio::time;
@@ -39,11 +42,15 @@ struct OCState {
     confirmed_signal_aspect: Option<SCILSSignalAspect>,
     confirmed_brightness: Option<SCILSBrightness>,
     conn_state: OCConnectionState,
+    expected_checksum: Option<Vec<u8>>,
 }
 
 struct RastaService {
     signal_main_to_send: Arc<RwLock<Option<SCILSMain>>>,
     brightness_to_send: Arc<RwLock<Option<SCILSBrightness>>>,
+    // checksum of the approved pin config of the OC, any config is accepted if not set
+    expected_checksum: Option<Vec<u8>>,
+    sci_cfg: SciConfig,
 }
 
 #[tonic::async_trait]
@@ -60,60 +67,62 @@ impl Rasta for RastaService {
             confirmed_signal_aspect: None,
             confirmed_brightness: None,
             conn_state: OCConnectionState::VersionRequestSent,
+            expected_checksum: self.expected_checksum.clone(),
         };
 
         let cloned_signal_main_to_send = self.signal_main_to_send.clone();
         let cloned_brightness_to_send = self.brightness_to_send.clone();
+        let sci_cfg = self.sci_cfg.clone();
 
         let output = async_stream::try_stream! {
             // begin handshake with sending a version request
             yield SciPacket {
-                message: SCITelegram::version_request(ProtocolType::SCIProtocolLS, "C", "S",SCI_LS_VERSION).into()
+                message: SCITelegram::version_request(ProtocolType::SCIProtocolLS, &sci_cfg.interlocking.sci_name, &sci_cfg.oc.sci_name, SCI_LS_VERSION).into()
             };
 
-            while let Some(sci_packet) = stream.next().await {
-                let sci_packet = sci_packet?;
-                let sci_telegram = sci_packet.message.as_slice().try_into()
-                    .unwrap_or_else(|e| panic!("Could not convert packet into SCITelegram: {:?}", e));
-                if let Some(sci_response) = handle_incoming_telegram(sci_telegram, &mut oc_state) {
-                    yield SciPacket {
-                        message: sci_response.into()
-                    };
-                    if oc_state.conn_state == OCConnectionState::Terminated {
-                        break;
+            // the stream of the OC is read while waiting for commands, so unsolicited status
+            // telegrams are matched right away and not to the next command
+            let mut interval = time::interval(Duration::from_millis(SEND_INTERVAL_MS));
+            loop {
+                let connected = oc_state.conn_state == OCConnectionState::Connected;
+                let sci_packet = tokio::select! {
+                    sci_packet = stream.next() => match sci_packet {
+                        Some(sci_packet) => Some(sci_packet),
+                        None => break,
+                    },
+                    _ = interval.tick(), if connected => None,
+                };
+                if let Some(sci_packet) = sci_packet {
+                    let sci_packet = sci_packet?;
+                    let sci_telegram = sci_packet.message.as_slice().try_into()
+                        .unwrap_or_else(|e| panic!("Could not convert packet into SCITelegram: {:?}", e));
+                    if let Some(sci_response) = handle_incoming_telegram(sci_telegram, &mut oc_state, &sci_cfg) {
+                        yield SciPacket {
+                            message: sci_response.into()
+                        };
+                        if oc_state.conn_state == OCConnectionState::Terminated {
+                            break;
+                        }
                     }
-                }
-                // if connected, wait until user sets a signal aspect, then send it
-                if oc_state.conn_state != OCConnectionState::Connected {
                     continue;
                 }
-                let mut interval = time::interval(Duration::from_millis(SEND_INTERVAL_MS));
-                let mut signal_aspect_telegram = None;
-                let mut brightness_telegram = None;
-                while let time = interval.tick().await {
+                // if connected, send the signal aspect and brightness set by the user
+                let mut telegrams = Vec::new();
+                {
                     let mut locked_signal_main = cloned_signal_main_to_send.write().unwrap();
-                    if let Some(signal_main) = *locked_signal_main {
-                        *locked_signal_main = None;
+                    if let Some(signal_main) = locked_signal_main.take() {
                         if signal_main != oc_state.confirmed_signal_aspect.clone().unwrap().main() {
-                            signal_aspect_telegram = Some(create_telegram_from_main(signal_main));
-                            break;
+                            telegrams.push(create_telegram_from_main(signal_main, &sci_cfg));
                         }
                     }
                     let mut locked_brightness = cloned_brightness_to_send.write().unwrap();
-                    if let Some(brightness) = *locked_brightness {
-                        *locked_brightness = None;
+                    if let Some(brightness) = locked_brightness.take() {
                         if brightness != oc_state.confirmed_brightness.clone().unwrap() {
-                            brightness_telegram = Some(create_telegram_from_brightness(brightness));
-                            break;
+                            telegrams.push(create_telegram_from_brightness(brightness, &sci_cfg));
                         }
                     }
                 }
-                if let Some(telegram) = signal_aspect_telegram {
-                    yield SciPacket {
-                        message: telegram.into()
-                    };
-                }
-                if let Some(telegram) = brightness_telegram {
+                for telegram in telegrams {
                     yield SciPacket {
                         message: telegram.into()
                     };
@@ -125,7 +134,7 @@ impl Rasta for RastaService {
     }
 }
 
-fn create_telegram_from_main(main: SCILSMain) -> SCITelegram {
+fn create_telegram_from_main(main: SCILSMain, sci_cfg: &SciConfig) -> SCITelegram {
     let signal_aspect = SCILSSignalAspect::new(
         main,
         Default::default(),
@@ -139,33 +148,73 @@ fn create_telegram_from_main(main: SCILSMain) -> SCITelegram {
         Default::default(),
         [0u8; 9],
     );
-    SCITelegram::scils_show_signal_aspect("C", "S", signal_aspect)
+    SCITelegram::scils_show_signal_aspect(
+        &sci_cfg.interlocking.sci_name,
+        &sci_cfg.oc.sci_name,
+        signal_aspect,
+    )
 }
 
-fn create_telegram_from_brightness(brightness: SCILSBrightness) -> SCITelegram {
-    SCITelegram::scils_change_brightness("C", "S", brightness)
+fn create_telegram_from_brightness(
+    brightness: SCILSBrightness,
+    sci_cfg: &SciConfig,
+) -> SCITelegram {
+    SCITelegram::scils_change_brightness(
+        &sci_cfg.interlocking.sci_name,
+        &sci_cfg.oc.sci_name,
+        brightness,
+    )
 }
 
-// MD5 (16 bytes)
-fn check_checksum(
-    sci_telegram: &SCITelegram,
-    received_checksum: &[u8],
-    remote_version: u8,
-    remote_check_result: SCIVersionCheckResult,
-) -> bool {
-    let pseudo_telegram = SCITelegram::version_response(
-        ProtocolType::SCIProtocolLS,
-        &*sci_telegram.sender,
-        &*sci_telegram.receiver,
-        remote_version,
-        remote_check_result,
-        &[0],
-    );
-    let computed_checksum = md5::compute::<Vec<u8>>(pseudo_telegram.into());
-    computed_checksum.as_slice() == received_checksum
+fn checksum_hex(checksum: &[u8]) -> String {
+    checksum
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// MD5 (16 bytes) as 32 hex digits, as printed by validate_config
+fn parse_checksum(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return Err(format!("CHECKSUM {} IS NOT 32 HEX DIGITS", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| format!("CHECKSUM {} IS NOT 32 HEX DIGITS", hex))
+        })
+        .collect()
+}
+
+// MD5 (16 bytes) over the pin config and the SCI name of the OC
+fn check_checksum(received_checksum: &[u8], expected_checksum: &Option<Vec<u8>>) -> bool {
+    match expected_checksum {
+        Some(expected_checksum) => expected_checksum.as_slice() == received_checksum,
+        None => {
+            println!(
+                "No expected checksum configured, accepting OC config {}",
+                checksum_hex(received_checksum)
+            );
+            true
+        }
+    }
 }
 
-fn handle_incoming_telegram(sci_telegram: SCITelegram, state: &mut OCState) -> Option<SCITelegram> {
+fn handle_incoming_telegram(
+    sci_telegram: SCITelegram,
+    state: &mut OCState,
+    sci_cfg: &SciConfig,
+) -> Option<SCITelegram> {
+    let interlocking_name = &*sci_cfg.interlocking.sci_name;
+    let oc_name = &*sci_cfg.oc.sci_name;
+    if sci_telegram.receiver != interlocking_name {
+        println!(
+            "Ignored telegram addressed to {} from {}, this interlocking is {}",
+            sci_telegram.receiver, sci_telegram.sender, interlocking_name
+        );
+        return None;
+    }
     if sci_telegram.message_type == SCIMessageType::scils_signal_aspect_status()
         && (state.conn_state == OCConnectionState::StatusBeginReceived
             || state.conn_state == OCConnectionState::Connected)
@@ -205,13 +254,29 @@ fn handle_incoming_telegram(sci_telegram: SCITelegram, state: &mut OCState) -> O
         if remote_check_result == SCIVersionCheckResult::VersionsAreEqual
             && remote_version == SCI_LS_VERSION
             && checksum_len > 0
-            && check_checksum(&sci_telegram, checksum, remote_version, remote_check_result)
+            && !check_checksum(checksum, &state.expected_checksum)
+        {
+            println!(
+                "OC runs an unapproved config (checksum {}, expected {})!",
+                checksum_hex(checksum),
+                checksum_hex(state.expected_checksum.as_deref().unwrap_or_default())
+            );
+            state.conn_state = OCConnectionState::Terminated;
+            return Some(SCITelegram::close(
+                ProtocolType::SCIProtocolLS,
+                interlocking_name,
+                oc_name,
+                SCICloseReason::ChecksumMismatch,
+            ));
+        } else if remote_check_result == SCIVersionCheckResult::VersionsAreEqual
+            && remote_version == SCI_LS_VERSION
+            && checksum_len > 0
         {
             state.conn_state = OCConnectionState::StatusRequestSent;
             return Some(SCITelegram::status_request(
                 ProtocolType::SCIProtocolLS,
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
+                interlocking_name,
+                oc_name,
             ));
         } else {
             println!(
@@ -221,8 +286,8 @@ fn handle_incoming_telegram(sci_telegram: SCITelegram, state: &mut OCState) -> O
             state.conn_state = OCConnectionState::Terminated;
             return Some(SCITelegram::release_for_maintenance(
                 ProtocolType::SCIProtocolLS,
-                &*sci_telegram.receiver,
-                &*sci_telegram.sender,
+                interlocking_name,
+                oc_name,
             ));
         }
     } else if sci_telegram.message_type == SCIMessageType::sci_status_begin()
@@ -240,8 +305,8 @@ fn handle_incoming_telegram(sci_telegram: SCITelegram, state: &mut OCState) -> O
         state.conn_state = OCConnectionState::Terminated;
         return Some(SCITelegram::close(
             ProtocolType::SCIProtocolLS,
-            &*sci_telegram.receiver,
-            &*sci_telegram.sender,
+            interlocking_name,
+            oc_name,
             SCICloseReason::ProtocolError,
         ));
     }
@@ -255,6 +320,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = format!("{}:{}", server_ip_addr, server_port)
         .parse()
         .unwrap();
+    let expected_checksum = std::env::args().nth(3).map(|hex| {
+        parse_checksum(&hex).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let sci_cfg = sci_config::get_sci_config();
 
     let signal_main_to_send = None;
     let signal_main_lock = RwLock::new(signal_main_to_send);
@@ -292,6 +364,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let rasta_service = RastaService {
         signal_main_to_send: signal_main_lock_send,
         brightness_to_send: brightness_lock_send,
+        expected_checksum,
+        sci_cfg,
     };
     let server = RastaServer::new(rasta_service);
     Server::builder().add_service(server).serve(addr).await?;
@@ -0,0 +1,266 @@
+use crate::io_config::PinConfig;
+use chrono::Local;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LampFault {
+    BurntOut,          // lamp stays dark on both lines
+    StuckOn,           // lamp is lit regardless of its outputs
+    BrokenFeedback,    // input never reports the lamp as lit
+    PrimaryLineFailed, // primary output does not reach the lamp
+    BackupLineFailed,  // backup output does not reach the lamp
+}
+
+impl FromStr for LampFault {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "burnt-out" => Ok(LampFault::BurntOut),
+            "stuck-on" => Ok(LampFault::StuckOn),
+            "broken-feedback" => Ok(LampFault::BrokenFeedback),
+            "primary-failed" => Ok(LampFault::PrimaryLineFailed),
+            "backup-failed" => Ok(LampFault::BackupLineFailed),
+            _ => Err(format!("UNKNOWN FAULT {}", s)),
+        }
+    }
+}
+
+pub type FaultMap = Arc<Mutex<HashMap<usize, HashSet<LampFault>>>>;
+
+// one lamp of the signal, wired like pins_output/pins_output_backup/pins_input at the same index
+struct Lamp {
+    output: String,
+    output_backup: String,
+    input: String,
+}
+
+pub struct SignalSimulator {
+    lamps: Vec<Lamp>,
+    master_pin: Option<String>,
+    faults: FaultMap,
+}
+
+impl SignalSimulator {
+    pub fn new(cfg: &PinConfig) -> Self {
+        let lamps = cfg
+            .pins_input
+            .iter()
+            .enumerate()
+            .map(|(index, input)| Lamp {
+                output: cfg.pins_output[index].to_string(),
+                output_backup: cfg.pins_output_backup[index].to_string(),
+                input: input.to_string(),
+            })
+            .collect();
+        Self {
+            lamps,
+            master_pin: cfg.master_pin.clone(),
+            faults: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn inputs(&self) -> Vec<String> {
+        self.lamps
+            .iter()
+            .map(|lamp| lamp.input.to_string())
+            .collect()
+    }
+
+    pub fn faults(&self) -> FaultMap {
+        self.faults.clone()
+    }
+
+    // value of the given input pin, None if the pin is not wired to a lamp
+    pub fn feedback<F>(&self, input: &str, mut read_output: F) -> Option<u8>
+    where
+        F: FnMut(&str) -> u8,
+    {
+        let faults = self.faults.lock().unwrap();
+        let no_faults = HashSet::new();
+        // the master pin switches the power of all lamps
+        let powered = match &self.master_pin {
+            Some(master_pin) => read_output(master_pin) == 1,
+            None => true,
+        };
+        let mut found = false;
+        let mut value = 0;
+        for (index, lamp) in self.lamps.iter().enumerate() {
+            if lamp.input != input {
+                continue;
+            }
+            found = true;
+            let lamp_faults = faults.get(&index).unwrap_or(&no_faults);
+            let primary = read_output(&lamp.output) == 1
+                && !lamp_faults.contains(&LampFault::PrimaryLineFailed);
+            let backup = read_output(&lamp.output_backup) == 1
+                && !lamp_faults.contains(&LampFault::BackupLineFailed);
+            let lit = powered
+                && (lamp_faults.contains(&LampFault::StuckOn)
+                    || (!lamp_faults.contains(&LampFault::BurntOut) && (primary || backup)));
+            if lit && !lamp_faults.contains(&LampFault::BrokenFeedback) {
+                value = 1;
+            }
+        }
+        if found {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    // starts the control socket and the fault script given in the pin config
+    pub fn start_fault_injection(&self, cfg: &PinConfig) {
+        let lamp_names: Vec<String> = self.lamps.iter().map(|l| l.output.to_string()).collect();
+        if let Some(addr) = cfg.simulator.control_socket.clone() {
+            let faults = self.faults();
+            let lamp_names = lamp_names.clone();
+            thread::spawn(move || listen(&addr, faults, lamp_names));
+        }
+        if let Some(path) = cfg.simulator.fault_script.clone() {
+            let faults = self.faults();
+            thread::spawn(move || run_script(&path, faults, lamp_names));
+        }
+    }
+}
+
+// a lamp is given either by its index or by its primary output pin
+fn find_lamp(lamp_names: &[String], lamp: &str) -> Result<usize, String> {
+    match lamp.parse::<usize>() {
+        Ok(index) if index < lamp_names.len() => Ok(index),
+        Ok(index) => Err(format!("NO LAMP WITH INDEX {}", index)),
+        Err(_) => lamp_names
+            .iter()
+            .position(|name| name == lamp)
+            .ok_or(format!("NO LAMP WITH OUTPUT {}", lamp)),
+    }
+}
+
+// commands:
+// - fault <lamp> <burnt-out|stuck-on|broken-feedback|primary-failed|backup-failed>
+// - repair <lamp> [<fault>]
+// - status
+// - sleep <milliseconds>
+pub fn handle_command(faults: &FaultMap, lamp_names: &[String], line: &str) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["fault", lamp, fault] => {
+            let index = match find_lamp(lamp_names, lamp) {
+                Ok(index) => index,
+                Err(e) => return e,
+            };
+            let fault = match fault.parse::<LampFault>() {
+                Ok(fault) => fault,
+                Err(e) => return e,
+            };
+            faults
+                .lock()
+                .unwrap()
+                .entry(index)
+                .or_default()
+                .insert(fault);
+            format!("INJECTED {:?} AT LAMP {}", fault, lamp_names[index])
+        }
+        ["repair", lamp] | ["repair", lamp, _] => {
+            let index = match find_lamp(lamp_names, lamp) {
+                Ok(index) => index,
+                Err(e) => return e,
+            };
+            let mut locked_faults = faults.lock().unwrap();
+            let lamp_faults = locked_faults.entry(index).or_default();
+            if let Some(fault) = words.get(2) {
+                match fault.parse::<LampFault>() {
+                    Ok(fault) => lamp_faults.remove(&fault),
+                    Err(e) => return e,
+                };
+            } else {
+                lamp_faults.clear();
+            }
+            format!("REPAIRED LAMP {}", lamp_names[index])
+        }
+        ["status"] => {
+            let locked_faults = faults.lock().unwrap();
+            lamp_names
+                .iter()
+                .enumerate()
+                .map(|(index, name)| {
+                    format!(
+                        "LAMP {} ({}): {:?}",
+                        index,
+                        name,
+                        locked_faults.get(&index).cloned().unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        ["sleep", ms] => match ms.parse::<u64>() {
+            Ok(ms) => {
+                thread::sleep(Duration::from_millis(ms));
+                format!("SLEPT {} MS", ms)
+            }
+            Err(_) => format!("INVALID DURATION {}", ms),
+        },
+        [] => String::new(),
+        _ => format!("UNKNOWN COMMAND {}", line.trim()),
+    }
+}
+
+fn listen(addr: &str, faults: FaultMap, lamp_names: Vec<String>) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("COULD NOT OPEN SIMULATOR CONTROL SOCKET {}: {}", addr, e);
+            return;
+        }
+    };
+    println!("SIMULATOR CONTROL SOCKET LISTENING ON {}", addr);
+    for stream in listener.incoming().flatten() {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => continue,
+        };
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            let answer = handle_command(&faults, &lamp_names, &line);
+            println!(
+                "{} SIMULATOR: {}",
+                Local::now().format("%d-%m-%Y %H:%M:%S"),
+                answer
+            );
+            if writeln!(writer, "{}", answer).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// executes the fault script line by line, lines starting with # are comments
+fn run_script(path: &str, faults: FaultMap, lamp_names: Vec<String>) {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("COULD NOT READ FAULT SCRIPT {}: {}", path, e);
+            return;
+        }
+    };
+    for line in script.lines() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        let answer = handle_command(&faults, &lamp_names, line);
+        if !answer.is_empty() {
+            println!(
+                "{} SIMULATOR: {}",
+                Local::now().format("%d-%m-%Y %H:%M:%S"),
+                answer
+            );
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+use rasta_rs::RastaConnection;
+use sci_rs::{SCICommand, SCIConnection, SCITelegram};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+use tonic::transport::Endpoint;
+use tonic::Request;
+
+use crate::rasta_grpc::rasta_client::RastaClient;
+use crate::rasta_grpc::SciPacket;
+use crate::sci_config::{SciConfig, TransportType};
+
+pub type TransportError = Box<dyn Error + Send + Sync>;
+
+// an open connection to the interlocking: telegrams given to outgoing are sent in order, received
+// packets arrive on incoming, which ends with the connection
+pub struct SciLink {
+    pub(crate) outgoing: mpsc::UnboundedSender<SCITelegram>,
+    pub(crate) incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+    // resolves once outgoing is dropped and the telegrams given to it are sent
+    pub(crate) flushed: oneshot::Receiver<()>,
+}
+
+#[tonic::async_trait]
+pub trait SciTransport: Send {
+    fn name(&self) -> String;
+    async fn connect(&mut self) -> Result<SciLink, TransportError>;
+}
+
+// the C++ rasta_grpc_bridge_udp, acting as RaSTA client and gRPC server for the OC
+pub struct GrpcTransport {
+    bridge_url: String,
+    keep_alive_interval: Duration,
+    keep_alive_timeout: Duration,
+}
+
+impl GrpcTransport {
+    pub fn new(bridge_ip_addr: &str, bridge_port: &str, sci_cfg: &SciConfig) -> Self {
+        Self {
+            bridge_url: format!("http://{}:{}", bridge_ip_addr, bridge_port),
+            keep_alive_interval: Duration::from_millis(sci_cfg.keep_alive_interval),
+            keep_alive_timeout: Duration::from_millis(sci_cfg.keep_alive_timeout),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SciTransport for GrpcTransport {
+    fn name(&self) -> String {
+        format!("the gRPC bridge at {}", self.bridge_url)
+    }
+
+    async fn connect(&mut self) -> Result<SciLink, TransportError> {
+        // a hung bridge stops answering the HTTP/2 pings, which fails the stream
+        let channel = Endpoint::from_shared(self.bridge_url.clone())?
+            .http2_keep_alive_interval(self.keep_alive_interval)
+            .keep_alive_timeout(self.keep_alive_timeout)
+            .keep_alive_while_idle(true)
+            .connect()
+            .await?;
+        let mut client = RastaClient::new(channel);
+
+        let (outgoing, mut outgoing_receiver) = mpsc::unbounded_channel::<SCITelegram>();
+        let (flushed_sender, flushed) = oneshot::channel();
+        let outbound = async_stream::stream! {
+            while let Some(telegram) = outgoing_receiver.recv().await {
+                yield SciPacket { message: telegram.into() };
+            }
+            let _ = flushed_sender.send(());
+        };
+        let mut inbound = client.stream(Request::new(outbound)).await?.into_inner();
+
+        // forwards the received packets until the stream ends or incoming is dropped
+        let (incoming_sender, incoming) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = incoming_sender.closed() => break,
+                    sci_packet = inbound.message() => match sci_packet {
+                        Ok(Some(sci_packet)) => {
+                            let _ = incoming_sender.send(sci_packet.message);
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("Error: GRPC STREAM FAILED: {}", e);
+                            break;
+                        }
+                    },
+                }
+            }
+        });
+
+        Ok(SciLink {
+            outgoing,
+            incoming,
+            flushed,
+        })
+    }
+}
+
+// native RaSTA connection of rasta-rs, the OC connects as RaSTA client to rasta_address; RaSTA
+// supervises the connection inside rasta-rs, the OC only learns about a dead interlocking when
+// run() returns
+pub struct RastaTransport {
+    sci_cfg: SciConfig,
+}
+
+impl RastaTransport {
+    pub fn new(sci_cfg: &SciConfig) -> Self {
+        Self {
+            sci_cfg: sci_cfg.clone(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SciTransport for RastaTransport {
+    fn name(&self) -> String {
+        format!("RaSTA at {}", self.sci_cfg.rasta_address)
+    }
+
+    async fn connect(&mut self) -> Result<SciLink, TransportError> {
+        let sci_cfg = self.sci_cfg.clone();
+        let (outgoing, mut outgoing_receiver) = mpsc::unbounded_channel::<SCITelegram>();
+        let (incoming_sender, incoming) = mpsc::unbounded_channel();
+        let (flushed_sender, flushed) = oneshot::channel();
+        let (connected_sender, connected) = oneshot::channel::<Result<(), String>>();
+
+        // rasta-rs blocks while the connection runs, its callback is polled for the next telegram
+        thread::spawn(move || {
+            let mut connection =
+                match RastaConnection::try_new(sci_cfg.rasta_address, sci_cfg.oc.rasta_id)
+                    .map_err(|e| format!("{:?}", e))
+                    .and_then(|conn| {
+                        let sci_name_rasta_id_mapping = HashMap::from([
+                            (sci_cfg.oc.sci_name.clone(), sci_cfg.oc.rasta_id),
+                            (
+                                sci_cfg.interlocking.sci_name.clone(),
+                                sci_cfg.interlocking.rasta_id,
+                            ),
+                        ]);
+                        SCIConnection::try_new(
+                            conn,
+                            sci_cfg.oc.sci_name.clone(),
+                            sci_name_rasta_id_mapping,
+                        )
+                        .map_err(|e| format!("{:?}", e))
+                    }) {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        let _ = connected_sender.send(Err(e));
+                        return;
+                    }
+                };
+            let _ = connected_sender.send(Ok(()));
+
+            let mut flushed_sender = Some(flushed_sender);
+            let result = connection.run(&sci_cfg.interlocking.sci_name, |received| {
+                if let Some(telegram) = received {
+                    if incoming_sender.send(telegram.into()).is_err() {
+                        return SCICommand::Disconnect;
+                    }
+                }
+                match outgoing_receiver.try_recv() {
+                    Ok(telegram) => SCICommand::Telegram(telegram),
+                    Err(TryRecvError::Empty) => SCICommand::Wait,
+                    Err(TryRecvError::Disconnected) => {
+                        if let Some(flushed_sender) = flushed_sender.take() {
+                            let _ = flushed_sender.send(());
+                        }
+                        SCICommand::Disconnect
+                    }
+                }
+            });
+            if let Err(e) = result {
+                eprintln!("Error: RASTA CONNECTION FAILED: {:?}", e);
+            }
+        });
+
+        connected.await??;
+        Ok(SciLink {
+            outgoing,
+            incoming,
+            flushed,
+        })
+    }
+}
+
+// number of command line arguments taken by the transport, only the gRPC bridge needs its address
+pub fn transport_arg_count(sci_cfg: &SciConfig) -> usize {
+    match sci_cfg.transport {
+        TransportType::Grpc => 2,
+        TransportType::Rasta => 0,
+    }
+}
+
+// the transport selected in the sci config, the bridge address is only used by the gRPC bridge
+pub fn get_transport(
+    sci_cfg: &SciConfig,
+    bridge_ip_addr_pos: usize,
+    bridge_port_pos: usize,
+) -> Box<dyn SciTransport> {
+    println!("SCI TRANSPORT {:?}", sci_cfg.transport);
+    match sci_cfg.transport {
+        TransportType::Grpc => {
+            let bridge_ip_addr = std::env::args().nth(bridge_ip_addr_pos).unwrap();
+            let bridge_port = std::env::args().nth(bridge_port_pos).unwrap();
+            Box::new(GrpcTransport::new(&bridge_ip_addr, &bridge_port, sci_cfg))
+        }
+        TransportType::Rasta => Box::new(RastaTransport::new(sci_cfg)),
+    }
+}
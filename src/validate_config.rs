@@ -0,0 +1,189 @@
+// only the config checks and the pin lookup of the receiver modules are used here
+#[allow(dead_code)]
+mod io_backend;
+#[allow(dead_code)]
+mod io_config;
+#[allow(dead_code)]
+mod sci_config;
+#[allow(dead_code)]
+mod simulator;
+
+use io_backend::{IOBackend, PiControlBackend, SimulatedBackend};
+use io_config::{aspect_output_pins, signal_name, IOBackendType, PinConfig, SIGNAL_ASPECTS};
+use std::env;
+use std::process::ExitCode;
+
+// lamp table: the output, backup and input pin of every lamp
+fn print_lamps(cfg: &PinConfig) {
+    println!(
+        "{:<6}{:<12}{:<12}{:<12}",
+        "LAMP", "OUTPUT", "BACKUP", "INPUT"
+    );
+    for (index, output) in cfg.pins_output.iter().enumerate() {
+        println!(
+            "{:<6}{:<12}{:<12}{:<12}",
+            index, output, cfg.pins_output_backup[index], cfg.pins_input[index]
+        );
+    }
+}
+
+// aspect table: X marks a lit lamp, F a flashing lamp, columns are the primary output pins
+fn print_aspects(cfg: &PinConfig) {
+    print!("{:<32}", "ASPECT");
+    for output in cfg.pins_output.iter() {
+        print!("{:<8}", output);
+    }
+    println!();
+    for main in SIGNAL_ASPECTS {
+        let name = signal_name(main);
+        let pattern = match cfg.signals.get(main) {
+            Some(pattern) => pattern,
+            None => continue,
+        };
+        let flashing = cfg.flashing.get(name);
+        print!("{:<32}", name);
+        for (index, value) in pattern.iter().enumerate() {
+            let flashes = flashing.is_some_and(|flashing| flashing.pins.get(index) == Some(&1));
+            let cell = match (*value, flashes) {
+                (1, true) => "F",
+                (1, false) => "X",
+                _ => "-",
+            };
+            print!("{:<8}", cell);
+        }
+        println!();
+    }
+    println!("MOST RESTRICTIVE ASPECT: {}", cfg.most_restrictive_aspect);
+}
+
+fn print_indicators(cfg: &PinConfig) {
+    for (name, indicator_cfg) in [
+        ("Zs3", &cfg.zs3),
+        ("Zs3v", &cfg.zs3v),
+        ("Zs2", &cfg.zs2),
+        ("Zs2v", &cfg.zs2v),
+    ] {
+        let indicator_cfg = match indicator_cfg {
+            Some(indicator_cfg) => indicator_cfg,
+            None => continue,
+        };
+        print!("{:<32}", name);
+        for pin in indicator_cfg.pins.iter() {
+            print!("{:<8}", pin);
+        }
+        println!();
+        let mut values: Vec<&String> = indicator_cfg.signals.keys().collect();
+        values.sort();
+        for value in values {
+            print!("{:<32}", value);
+            for pin_value in indicator_cfg.signals[value].iter() {
+                print!("{:<8}", if *pin_value == 1 { "X" } else { "-" });
+            }
+            println!();
+        }
+        println!();
+    }
+}
+
+// every pin the OC writes or reads, each name once
+fn config_pins(cfg: &PinConfig) -> Vec<String> {
+    let mut pins: Vec<String> = Vec::new();
+    for pin in aspect_output_pins(cfg)
+        .chain(cfg.pins_input.iter())
+        .chain(std::iter::once(&cfg.day_night_pin))
+        .chain(cfg.master_pin.iter())
+    {
+        if !pins.contains(pin) {
+            pins.push(pin.to_string());
+        }
+    }
+    pins
+}
+
+// resolves the pin names against the variables of the process image, returns false if a pin
+// is not found, the simulated backend knows the pins of a RevPi DIO and injects no faults here
+fn resolve_pins(cfg: &PinConfig) -> bool {
+    let mut io: Box<dyn IOBackend> = match io_backend::backend_type(cfg, 2) {
+        IOBackendType::PiControl => Box::new(PiControlBackend::new()),
+        IOBackendType::Simulated => Box::new(SimulatedBackend::new(cfg)),
+    };
+    let mut all_found = true;
+    println!("{:<12}{:<10}{:<4}", "PIN", "ADDRESS", "BIT");
+    for pin in config_pins(cfg) {
+        match io.resolve_pin(&pin) {
+            Some(pin_address) => println!(
+                "{:<12}{:<10}{:<4}",
+                pin, pin_address.address, pin_address.bit
+            ),
+            None => {
+                println!("{:<12}NOT FOUND", pin);
+                all_found = false;
+            }
+        }
+    }
+    io_backend::warn_split_aspect_writes(&mut *io, cfg);
+    all_found
+}
+
+// usage: validate_config <pin config> [picontrol|simulated|-] [sci name|-] [sci config]
+// the pin names are only resolved if a backend is given, the checksum the OC reports in its
+// version response depends on its SCI name (default: the OC in the sci config, by default
+// ./config/sci_config.toml)
+fn main() -> ExitCode {
+    let config_file_path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!(
+                "Error: USAGE: validate_config <pin config> [picontrol|simulated|-] [sci name|-] [sci config]"
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let cfg = match io_config::read_config(&config_file_path) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("CONFIG FILE {} IS VALID", config_file_path);
+    println!();
+    print_lamps(&cfg);
+    println!();
+    print_aspects(&cfg);
+    println!();
+    print_indicators(&cfg);
+    // the checksum is only informative, the pin config is valid without it
+    let sci_config_path = env::args()
+        .nth(4)
+        .unwrap_or_else(|| sci_config::SCI_CONFIG_PATH.to_string());
+    let sci_name = match env::args().nth(3).filter(|sci_name| sci_name != "-") {
+        Some(sci_name) => Some(sci_name),
+        None => match sci_config::read_sci_config(&sci_config_path) {
+            Ok(sci_cfg) => Some(sci_cfg.oc.sci_name),
+            Err(e) => {
+                println!(
+                    "NOTE: NO CONFIG CHECKSUM, GIVE THE SCI NAME OR THE SCI CONFIG: {}",
+                    e
+                );
+                None
+            }
+        },
+    };
+    if let Some(sci_name) = sci_name {
+        println!(
+            "CONFIG CHECKSUM FOR SCI NAME {}: {:x}",
+            sci_name,
+            io_config::config_checksum(&cfg, &sci_name)
+        );
+    }
+    println!();
+    if env::args().nth(2).is_some_and(|backend| backend != "-") && !resolve_pins(&cfg) {
+        eprintln!("Error: NOT ALL PINS FOUND IN THE PROCESS IMAGE");
+        return ExitCode::FAILURE;
+    }
+    if cfg.backend == IOBackendType::Simulated {
+        println!("NOTE: THE CONFIG SELECTS THE SIMULATED BACKEND");
+    }
+    ExitCode::SUCCESS
+}